@@ -1,18 +1,294 @@
 #![no_std]
 
+use core::net::{IpAddr, Ipv6Addr};
 
+/// Maps an IPv4 address, as read from the header, to its IPv4-mapped IPv6 form (::ffff:a.b.c.d)
+#[inline(always)]
+pub fn ipv4_mapped(addr: u32) -> [u8; 16] {
+    let octets = addr.to_ne_bytes();
+    [
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, octets[0], octets[1], octets[2], octets[3],
+    ]
+}
 
-#[derive(Debug)]
+pub const IPPROTO_ICMP: u8 = 1;
+pub const IPPROTO_TCP: u8 = 6;
+pub const IPPROTO_UDP: u8 = 17;
+pub const IPPROTO_ICMPV6: u8 = 58;
+
+/// Key of the FLOW_INFO_TABLE map, oriented as seen by the side that sent the first packet.
+/// Addresses are in network order, IPv4 ones are stored as IPv4-mapped IPv6 addresses.
+/// ICMP/ICMPv6 flows have both ports set to 0, as in CICFlowMeter, so that a request and its
+/// reply (e.g. echo request 8/0 and echo reply 0/0) are the two directions of the same flow,
+/// their type and code are recorded in `FlowInfo` instead.
+#[derive(Debug, Clone, Copy)]
 #[repr(C)]
-pub struct EventInfo {
-    pub ip_src: u32,
-    pub ip_dst: u32,
+pub struct FlowKey {
+    pub l4_proto: u8,
+    pub _pad1: u8,
+    // innermost 802.1Q tag, 0 when untagged
+    pub vlan_id: u16,
+    pub ip_src: [u8; 16],
+    pub ip_dst: [u8; 16],
     pub port_src: u16,
     pub port_dst: u16,
-    
+}
+
+impl FlowKey {
+    #[inline(always)]
+    pub fn reversed(&self) -> Self {
+        FlowKey {
+            l4_proto: self.l4_proto,
+            _pad1: 0,
+            vlan_id: self.vlan_id,
+            ip_src: self.ip_dst,
+            ip_dst: self.ip_src,
+            port_src: self.port_dst,
+            port_dst: self.port_src,
+        }
+    }
+
+    pub fn is_icmp(&self) -> bool {
+        self.l4_proto == IPPROTO_ICMP || self.l4_proto == IPPROTO_ICMPV6
+    }
+
+    pub fn src_addr(&self) -> IpAddr {
+        Ipv6Addr::from(self.ip_src).to_canonical()
+    }
+
+    pub fn dst_addr(&self) -> IpAddr {
+        Ipv6Addr::from(self.ip_dst).to_canonical()
+    }
+}
+
+// TCP flags, same bit positions as in the TCP header
+pub const TCP_FIN: u8 = 0x01;
+pub const TCP_SYN: u8 = 0x02;
+pub const TCP_RST: u8 = 0x04;
+pub const TCP_PSH: u8 = 0x08;
+pub const TCP_ACK: u8 = 0x10;
+pub const TCP_URG: u8 = 0x20;
+
+// why a flow ended, stored in `FlowInfo::end_reason`
+pub const FLOW_ACTIVE: u64 = 0;
+pub const FLOW_END_FIN: u64 = 1;
+pub const FLOW_END_RST: u64 = 2;
+pub const FLOW_END_IDLE: u64 = 3;
+
+// packet directions, forward is the direction of the first packet of the flow
+pub const DIR_FWD: u8 = 0;
+pub const DIR_BWD: u8 = 1;
+
+/// L4 payload bytes of a packet, what the CIC-IDS2017 packet length columns count.
+/// `ip_hdr_len` includes IPv4 options and IPv6 extension headers.
+#[inline(always)]
+pub fn payload_len(ip_len: u64, ip_hdr_len: u64, l4_hdr_len: u64) -> u64 {
+    ip_len.saturating_sub(ip_hdr_len + l4_hdr_len)
+}
+
+/// Counters of one direction of a flow, times are `bpf_ktime_get_ns` nanoseconds and lengths
+/// are `payload_len` bytes
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+pub struct DirectionStats {
     pub num_packets: u64,
-    pub len: u64, 
     pub total_len: u64,
-    pub iat: u64,
+    pub sum_sq_len: u64,
+    pub min_len: u64,
+    pub max_len: u64,
+    // sum of the gaps between consecutive packets of this direction
     pub total_iat: u64,
+    // sum of the squared gaps in microseconds, nanoseconds would overflow after a few seconds
+    pub sum_sq_iat_us: u64,
+    pub last_packet_ts: u64,
+}
+
+impl DirectionStats {
+    /// Accounts a packet and returns its inter arrival time (0 for the first one)
+    #[inline(always)]
+    fn update(&mut self, len: u64, ts: u64) -> u64 {
+        let iat = if self.num_packets == 0 {
+            0
+        } else {
+            ts - self.last_packet_ts
+        };
+
+        if self.num_packets == 0 || len < self.min_len {
+            self.min_len = len;
+        }
+        if len > self.max_len {
+            self.max_len = len;
+        }
+
+        // clamped so the square always fits in a u64
+        let iat_us = (iat / 1000).min(u32::MAX as u64);
+
+        self.num_packets += 1;
+        self.total_len += len;
+        self.sum_sq_len += len * len;
+        self.total_iat += iat;
+        self.sum_sq_iat_us = self.sum_sq_iat_us.saturating_add(iat_us * iat_us);
+        self.last_packet_ts = ts;
+
+        iat
+    }
+}
+
+/// Value of the FLOW_INFO_TABLE map
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+pub struct FlowInfo {
+    pub first_packet_ts: u64,
+    // last packet accounted in the window
+    pub last_packet_ts: u64,
+    // last packet seen, including the ones after the window
+    pub last_seen_ts: u64,
+    pub end_reason: u64,
+    pub fwd: DirectionStats,
+    pub bwd: DirectionStats,
+    // packets carrying IPv4 options or IPv6 extension headers
+    pub ip_options_packets: u64,
+    // packets with each TCP flag set, both directions
+    pub fin_count: u32,
+    pub syn_count: u32,
+    pub rst_count: u32,
+    pub psh_count: u32,
+    pub ack_count: u32,
+    pub urg_count: u32,
+    // type and code of the first packet of ICMP/ICMPv6 flows, the request for echo flows
+    pub icmp_type: u8,
+    pub icmp_code: u8,
+    pub _pad: [u8; 6],
+}
+
+impl FlowInfo {
+    #[inline(always)]
+    pub fn new(ts: u64) -> Self {
+        FlowInfo {
+            first_packet_ts: ts,
+            last_packet_ts: ts,
+            last_seen_ts: ts,
+            ..Default::default()
+        }
+    }
+
+    #[inline(always)]
+    pub fn is_closed(&self) -> bool {
+        self.end_reason != FLOW_ACTIVE
+    }
+
+    #[inline(always)]
+    pub fn num_packets(&self) -> u64 {
+        self.fwd.num_packets + self.bwd.num_packets
+    }
+
+    /// Accounts a packet seen in `direction` and returns its inter arrival time within that direction
+    #[inline(always)]
+    pub fn update(&mut self, direction: u8, len: u64, ts: u64, ip_options: bool, tcp_flags: u8) -> u64 {
+        self.last_packet_ts = ts;
+        self.ip_options_packets += ip_options as u64;
+
+        self.fin_count += (tcp_flags & TCP_FIN != 0) as u32;
+        self.syn_count += (tcp_flags & TCP_SYN != 0) as u32;
+        self.rst_count += (tcp_flags & TCP_RST != 0) as u32;
+        self.psh_count += (tcp_flags & TCP_PSH != 0) as u32;
+        self.ack_count += (tcp_flags & TCP_ACK != 0) as u32;
+        self.urg_count += (tcp_flags & TCP_URG != 0) as u32;
+
+        if direction == DIR_FWD {
+            self.fwd.update(len, ts)
+        } else {
+            self.bwd.update(len, ts)
+        }
+    }
 }
+
+// emit modes of `FlowConfig`
+pub const EMIT_EVERY_PACKET: u32 = 0;
+pub const EMIT_EVERY_NTH: u32 = 1;
+pub const EMIT_WINDOW_END: u32 = 2;
+
+pub const DEFAULT_WINDOW: u32 = 10;
+
+/// Single entry of the CONFIG array map, set by userspace before attaching the program.
+/// A zeroed entry tracks `DEFAULT_WINDOW` packets and emits an event for each of them.
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+pub struct FlowConfig {
+    // packets tracked per flow window
+    pub window: u32,
+    pub emit_mode: u32,
+    // cadence of EMIT_EVERY_NTH
+    pub emit_every: u32,
+    pub _pad: u32,
+    // idle time after a full window before tracking restarts, 0 never restarts
+    pub cooldown_ns: u64,
+}
+
+impl FlowConfig {
+    #[inline(always)]
+    pub fn window(&self) -> u64 {
+        if self.window == 0 {
+            DEFAULT_WINDOW as u64
+        } else {
+            self.window as u64
+        }
+    }
+
+    /// Whether the `num_packets`-th packet of the window is reported to userspace
+    #[inline(always)]
+    pub fn should_emit(&self, num_packets: u64) -> bool {
+        match self.emit_mode {
+            EMIT_EVERY_NTH => num_packets.is_multiple_of(self.emit_every.max(1) as u64),
+            EMIT_WINDOW_END => num_packets == self.window(),
+            _ => true,
+        }
+    }
+}
+
+/// Flow snapshot sent to userspace after a packet has been accounted, or when the flow ends
+/// (`flow.end_reason` set), either on TCP FIN/RST or exported by the idle sweeper
+#[derive(Debug)]
+#[repr(C)]
+pub struct EventInfo {
+    pub key: FlowKey,
+    // direction of the packet that triggered the event
+    pub direction: u8,
+    // payload bytes of that packet
+    pub len: u64,
+    pub iat: u64,
+    pub flow: FlowInfo,
+}
+
+// verdicts stored in the BLOCKLIST map
+pub const VERDICT_PASS: u32 = 0;
+pub const VERDICT_DROP: u32 = 1;
+
+/// Entry of the BLOCKLIST map, keyed by source address (same encoding as `FlowKey`).
+/// `expires_at` is a `bpf_ktime_get_ns` timestamp, 0 means the entry never expires.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct BlockEntry {
+    pub verdict: u32,
+    pub _pad: u32,
+    pub expires_at: u64,
+}
+
+impl BlockEntry {
+    #[inline(always)]
+    pub fn is_active(&self, now: u64) -> bool {
+        self.expires_at == 0 || now < self.expires_at
+    }
+}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for BlockEntry {}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for FlowConfig {}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for FlowKey {}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for FlowInfo {}
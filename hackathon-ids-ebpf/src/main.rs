@@ -5,52 +5,96 @@ use aya_ebpf::{
     bindings::xdp_action,
     helpers::bpf_ktime_get_ns,
     macros::{map, xdp},
-    maps::{LruHashMap, RingBuf},
+    maps::{Array, HashMap, LruHashMap, RingBuf},
     programs::XdpContext,
 };
 use aya_log_ebpf::{debug, info};
-use hackathon_ids_common::EventInfo;
+use hackathon_ids_common::{
+    ipv4_mapped, payload_len, BlockEntry, EventInfo, FlowConfig, FlowInfo, FlowKey, DIR_BWD,
+    DIR_FWD, FLOW_ACTIVE, FLOW_END_FIN, FLOW_END_RST, TCP_ACK, TCP_FIN, TCP_PSH, TCP_RST, TCP_SYN,
+    TCP_URG, VERDICT_DROP,
+};
+
+use core::mem;
 
 use network_types::{
     eth::{EthHdr, EtherType},
-    ip::{IpProto, Ipv4Hdr},
+    ip::{IpProto, Ipv4Hdr, Ipv6Hdr},
     tcp::TcpHdr,
     udp::UdpHdr,
 };
 
 
+// 802.1Q / 802.1ad tag following the MAC addresses
 #[repr(C)]
-#[derive(Debug)]
-struct FlowKey {
-    l4_proto: u8,
-    _pad1: u8,
-    _pad2: u16,
-    ip_src: u32,
-    ip_dst: u32,
-    port_src: u16,
-    port_dst: u16,
+struct VlanHdr {
+    tci: u16,
+    ether_type: u16,
 }
 
+impl VlanHdr {
+    const LEN: usize = mem::size_of::<VlanHdr>();
+}
 
+// ether types in network order, as read from the packet
+const ETH_P_IP: u16 = EtherType::Ipv4 as u16;
+const ETH_P_IPV6: u16 = EtherType::Ipv6 as u16;
+const ETH_P_LOOP: u16 = EtherType::Loop as u16;
+const ETH_P_FIBRE_CHANNEL: u16 = EtherType::FibreChannel as u16;
+const ETH_P_8021Q: u16 = 0x8100_u16.to_be();
+const ETH_P_8021AD: u16 = 0x88a8_u16.to_be();
 
+// max number of VLAN tags parsed (QinQ)
+const MAX_VLAN_TAGS: usize = 2;
+
+// ICMP and ICMPv6 headers: type, code, checksum and 4 bytes of id/sequence, MTU...
+const ICMP_HDR_LEN: u64 = 8;
+
+// leading type and code shared by the ICMP and ICMPv6 headers
+#[repr(C)]
+struct IcmpHdr {
+    type_: u8,
+    code: u8,
+}
 
+// generic layout shared by the hop-by-hop, routing, fragment, destination options and AH headers
 #[repr(C)]
-#[derive(Debug, Default)]
-struct FlowInfo {
-    num_packets: u64,
-    last_packet_ts: u64,
-    total_len: u64,
-    total_iat: u64,
+struct Ipv6ExtHdr {
+    next_hdr: IpProto,
+    hdr_ext_len: u8,
+}
+
+// max number of IPv6 extension headers skipped before the L4 header
+const IPV6_MAX_EXT_HDRS: usize = 4;
+
+// L3 fields needed to track a flow, addresses are IPv6 or IPv4-mapped
+struct L3Info {
+    ip_src: [u8; 16],
+    ip_dst: [u8; 16],
+    proto: IpProto,
+    len: u16,
+    l4_offset: usize,
+    // IPv4 options or IPv6 extension headers present
+    ip_options: bool,
 }
 
+
+// tracking window and emit cadence, set from the userspace CLI
+#[map(name = "CONFIG")]
+static CONFIG: Array<FlowConfig> = Array::<FlowConfig>::with_max_entries(1, 0);
+
 #[map(name = "FLOW_INFO_TABLE")]
 static mut FLOW_INFO_TABLE: LruHashMap<FlowKey, FlowInfo> =
     LruHashMap::<FlowKey, FlowInfo>::with_max_entries(1024, 0);
 
+// source addresses flagged by the userspace detector
+#[map(name = "BLOCKLIST")]
+static mut BLOCKLIST: HashMap<[u8; 16], BlockEntry> =
+    HashMap::<[u8; 16], BlockEntry>::with_max_entries(4096, 0);
+
 #[map]
 static EVENTS: RingBuf = RingBuf::with_byte_size(1024 * 256, 0);
 
-use core::mem;
 // utility to get access to packet offset
 #[inline(always)]
 fn ptr_at<T>(ctx: &XdpContext, offset: usize) -> Result<*const T, ()> {
@@ -65,6 +109,126 @@ fn ptr_at<T>(ctx: &XdpContext, offset: usize) -> Result<*const T, ()> {
     Ok((start + offset) as *const T)
 }
 
+// verdict for a blocked source address, expired entries are removed
+#[inline(always)]
+fn check_blocklist(ctx: &XdpContext, source_addr: &[u8; 16], ts: u64) -> Option<u32> {
+    let entry = unsafe { BLOCKLIST.get(source_addr) }?;
+
+    if !entry.is_active(ts) {
+        unsafe {
+            let _ = BLOCKLIST.remove(source_addr);
+        }
+        return None;
+    }
+
+    if entry.verdict == VERDICT_DROP {
+        debug!(ctx, "drop blocked source {:i}", *source_addr);
+        return Some(xdp_action::XDP_DROP);
+    }
+
+    Some(xdp_action::XDP_PASS)
+}
+
+#[inline(always)]
+fn parse_ipv4(ctx: &XdpContext, l3_offset: usize) -> Result<L3Info, ()> {
+    let ipv4hdr: *const Ipv4Hdr = ptr_at(ctx, l3_offset)?;
+
+    // IHL is a 4 bit field, masking keeps the header length bounded (<= 60) for the verifier
+    let hdr_len = (unsafe { (*ipv4hdr).ihl() } & 0x0f) as usize * 4;
+    if hdr_len < Ipv4Hdr::LEN {
+        return Err(());
+    }
+
+    Ok(L3Info {
+        ip_src: ipv4_mapped(unsafe { (*ipv4hdr).src_addr }),
+        ip_dst: ipv4_mapped(unsafe { (*ipv4hdr).dst_addr }),
+        proto: unsafe { (*ipv4hdr).proto },
+        len: u16::from_be(unsafe { (*ipv4hdr).tot_len }),
+        l4_offset: l3_offset + hdr_len,
+        ip_options: hdr_len > Ipv4Hdr::LEN,
+    })
+}
+
+#[inline(always)]
+fn parse_ipv6(ctx: &XdpContext, l3_offset: usize) -> Result<L3Info, ()> {
+    let ipv6hdr: *const Ipv6Hdr = ptr_at(ctx, l3_offset)?;
+
+    let mut proto = unsafe { (*ipv6hdr).next_hdr };
+    let mut offset = l3_offset + Ipv6Hdr::LEN;
+
+    // skip the extension headers in front of the L4 header
+    for _ in 0..IPV6_MAX_EXT_HDRS {
+        if !matches!(
+            proto,
+            IpProto::HopOpt | IpProto::Ipv6Route | IpProto::Ipv6Frag | IpProto::Ipv6Opts | IpProto::Ah
+        ) {
+            break;
+        }
+
+        let ext: *const Ipv6ExtHdr = ptr_at(ctx, offset)?;
+        let hdr_ext_len = unsafe { (*ext).hdr_ext_len } as usize;
+        let hdr_len = match proto {
+            IpProto::Ipv6Frag => 8,
+            IpProto::Ah => (hdr_ext_len + 2) * 4,
+            _ => (hdr_ext_len + 1) * 8,
+        };
+
+        proto = unsafe { (*ext).next_hdr };
+        offset += hdr_len;
+    }
+
+    Ok(L3Info {
+        ip_src: unsafe { (*ipv6hdr).src_addr.in6_u.u6_addr8 },
+        ip_dst: unsafe { (*ipv6hdr).dst_addr.in6_u.u6_addr8 },
+        proto,
+        len: u16::from_be(unsafe { (*ipv6hdr).payload_len }) + Ipv6Hdr::LEN as u16,
+        l4_offset: offset,
+        ip_options: offset > l3_offset + Ipv6Hdr::LEN,
+    })
+}
+
+#[inline(always)]
+fn tcp_flags(tcphdr: *const TcpHdr) -> u8 {
+    let hdr = unsafe { &*tcphdr };
+    let mut flags = 0;
+    if hdr.fin() != 0 {
+        flags |= TCP_FIN;
+    }
+    if hdr.syn() != 0 {
+        flags |= TCP_SYN;
+    }
+    if hdr.rst() != 0 {
+        flags |= TCP_RST;
+    }
+    if hdr.psh() != 0 {
+        flags |= TCP_PSH;
+    }
+    if hdr.ack() != 0 {
+        flags |= TCP_ACK;
+    }
+    if hdr.urg() != 0 {
+        flags |= TCP_URG;
+    }
+    flags
+}
+
+// snapshot of the flow after the packet, sent to userspace
+#[inline(always)]
+fn emit_event(key: &FlowKey, direction: u8, len: u64, iat: u64, data_ptr: *mut FlowInfo) {
+    if let Some(mut buf) = EVENTS.reserve::<EventInfo>(0) {
+        unsafe {
+            let info = buf.as_mut_ptr();
+            (*info).key = *key;
+            (*info).direction = direction;
+            (*info).len = len;
+            (*info).iat = iat;
+            (*info).flow = *data_ptr;
+        };
+
+        buf.submit(0);
+    }
+}
+
 #[xdp]
 pub fn hackathon_ids(ctx: XdpContext) -> u32 {
     match try_hackathon_ids(ctx) {
@@ -74,21 +238,33 @@ pub fn hackathon_ids(ctx: XdpContext) -> u32 {
 }
 
 fn try_hackathon_ids(ctx: XdpContext) -> Result<u32, ()> {
-    let ethhdr: *const EthHdr = ptr_at(&ctx, 0)?; //
+    // ether type is the last field of the ethernet header
+    let mut ether_type = unsafe { *ptr_at::<u16>(&ctx, EthHdr::LEN - mem::size_of::<u16>())? };
 
     let ts = unsafe { bpf_ktime_get_ns() };
 
-    match unsafe { (*ethhdr).ether_type } {
-        EtherType::Ipv4 => {}
-        EtherType::Ipv6 => {
-            debug!(&ctx, "IPV6");
-            return Ok(xdp_action::XDP_PASS);
+    // skip up to two VLAN tags, the innermost VLAN ID identifies the flow
+    let mut l3_offset = EthHdr::LEN;
+    let mut vlan_id = 0;
+    for _ in 0..MAX_VLAN_TAGS {
+        if ether_type != ETH_P_8021Q && ether_type != ETH_P_8021AD {
+            break;
         }
-        EtherType::Loop => {
+
+        let vlanhdr: *const VlanHdr = ptr_at(&ctx, l3_offset)?;
+        vlan_id = u16::from_be(unsafe { (*vlanhdr).tci }) & 0x0fff;
+        ether_type = unsafe { (*vlanhdr).ether_type };
+        l3_offset += VlanHdr::LEN;
+    }
+
+    let l3 = match ether_type {
+        ETH_P_IP => parse_ipv4(&ctx, l3_offset)?,
+        ETH_P_IPV6 => parse_ipv6(&ctx, l3_offset)?,
+        ETH_P_LOOP => {
             debug!(&ctx, "Loop");
             return Ok(xdp_action::XDP_PASS);
         }
-        EtherType::FibreChannel => {
+        ETH_P_FIBRE_CHANNEL => {
             debug!(&ctx, "fibre");
             return Ok(xdp_action::XDP_PASS);
         }
@@ -96,128 +272,175 @@ fn try_hackathon_ids(ctx: XdpContext) -> Result<u32, ()> {
             debug!(&ctx, "other");
             return Ok(xdp_action::XDP_PASS);
         }
+    };
+
+    let source_addr = l3.ip_src;
+    let dest_addr = l3.ip_dst;
+    let proto = l3.proto;
+    let iplen = l3.len;
+
+    if l3.ip_options {
+        debug!(&ctx, "ip options from {:i}", source_addr);
     }
 
-    let ipv4hdr: *const Ipv4Hdr = ptr_at(&ctx, EthHdr::LEN)?;
-    let source_addr = u32::from_be(unsafe { (*ipv4hdr).src_addr });
-    let dest_addr = u32::from_be(unsafe { (*ipv4hdr).dst_addr });
-    let proto = unsafe { (*ipv4hdr).proto };
-    let iplen = unsafe { (*ipv4hdr).tot_len };
+    if let Some(action) = check_blocklist(&ctx, &source_addr, ts) {
+        return Ok(action);
+    }
 
-    let (source_port, dest_port) = match proto {
+    let (source_port, dest_port, tcp_flags, l4_hdr_len) = match proto {
         IpProto::Tcp => {
-            let tcphdr: *const TcpHdr = ptr_at(&ctx, EthHdr::LEN + Ipv4Hdr::LEN)?;
+            let tcphdr: *const TcpHdr = ptr_at(&ctx, l3.l4_offset)?;
             (
                 u16::from_be(unsafe { (*tcphdr).source }),
                 u16::from_be(unsafe { (*tcphdr).dest }),
+                tcp_flags(tcphdr),
+                // data offset in 32 bit words, includes the TCP options
+                (unsafe { (*tcphdr).doff() } & 0x0f) as u64 * 4,
             )
         }
         IpProto::Udp => {
-            let udphdr: *const UdpHdr = ptr_at(&ctx, EthHdr::LEN + Ipv4Hdr::LEN)?;
+            let udphdr: *const UdpHdr = ptr_at(&ctx, l3.l4_offset)?;
             (
                 u16::from_be(unsafe { (*udphdr).source }),
                 u16::from_be(unsafe { (*udphdr).dest }),
+                0,
+                UdpHdr::LEN as u64,
             )
         }
-        _ => return Err(()),
+        // ICMP flows have no ports, type and code differ between a request and its reply
+        IpProto::Icmp | IpProto::Ipv6Icmp => (0, 0, 0, ICMP_HDR_LEN),
+        _ => {
+            debug!(&ctx, "untracked proto {}", proto as u8);
+            return Ok(xdp_action::XDP_PASS);
+        }
+    };
+
+    // ICMP type and code are recorded in the flow rather than in its key
+    let (icmp_type, icmp_code) = match proto {
+        IpProto::Icmp | IpProto::Ipv6Icmp => {
+            let icmphdr: *const IcmpHdr = ptr_at(&ctx, l3.l4_offset)?;
+            unsafe { ((*icmphdr).type_, (*icmphdr).code) }
+        }
+        _ => (0, 0),
     };
 
+    // the dataset counts payload bytes, not the IP length
+    let len = payload_len(iplen as u64, (l3.l4_offset - l3_offset) as u64, l4_hdr_len);
+
     let key = FlowKey {
         l4_proto: proto as u8,
         _pad1: 0,
-        _pad2: 0,
+        vlan_id,
         ip_src: source_addr,
         ip_dst: dest_addr,
         port_src: source_port,
         port_dst: dest_port,
     };
+    let reversed_key = key.reversed();
 
-    let reversed_key = FlowKey {
-        l4_proto: proto as u8,
-        _pad1: 0,
-        _pad2: 0,
-        ip_src: dest_addr,
-        ip_dst: source_addr,
-        port_src: dest_port,
-        port_dst: source_port,
-    };
-
-    if let Some(data_ptr) = unsafe {
-        FLOW_INFO_TABLE
-            .get_ptr_mut(&key)
-            .or_else(|| FLOW_INFO_TABLE.get_ptr_mut(&reversed_key))
-    } {
-        if dest_port < source_port && unsafe { (*data_ptr).num_packets < 10 } {
-            // DL / BW direction only for a number of packets
-
-            let total_packets = unsafe { (*data_ptr).num_packets + 1 };
-            let total_len = unsafe { (*data_ptr).total_len + iplen as u64 };
-            let delta: u64 = unsafe { ts - (*data_ptr).last_packet_ts };
-            let total_delta = unsafe { (*data_ptr).total_iat + delta };
-            unsafe {
-                (*data_ptr).num_packets = total_packets;
-                (*data_ptr).last_packet_ts = ts;
-                (*data_ptr).total_len = total_len;
-                (*data_ptr).total_iat = total_delta;
-            }
-
-            debug!(
-                &ctx,
-                "{} inc flow {} {}:{} -> {}:{} packets number {} len: {} total_len: {} delta: {} total: {}",
-                ts,
-                proto as u8,
-                source_addr,
-                source_port,
-                dest_addr,
-                dest_port,
-                total_packets,
-                iplen,
-                total_len, 
-                delta,
-                total_len
-            );
-
-            if let Some(mut buf) = EVENTS.reserve::<EventInfo>(0) {
-                unsafe {
-                    let info = buf.as_mut_ptr();
-                    (*info).ip_src = source_addr;
-                    (*info).ip_dst = dest_addr;
-                    (*info).port_dst = dest_port;
-                    (*info).port_src = source_port;
-                    (*info).num_packets = total_packets;
-                    (*info).len = iplen as u64;
-                    (*info).total_len = total_len;
-                    (*info).iat = delta;
-                    (*info).total_iat = total_delta;
-                    
-
-                };
-
-                buf.submit(0);
-            }
-        }
+    // the flow is keyed by the side that sent its first packet (forward direction)
+    let (flow_key, direction, data_ptr) = if let Some(data_ptr) =
+        unsafe { FLOW_INFO_TABLE.get_ptr_mut(&key) }
+    {
+        (key, DIR_FWD, data_ptr)
+    } else if let Some(data_ptr) = unsafe { FLOW_INFO_TABLE.get_ptr_mut(&reversed_key) } {
+        (reversed_key, DIR_BWD, data_ptr)
     } else {
-        let data = FlowInfo {
-            last_packet_ts: ts,
-            ..Default::default()
+        let info = FlowInfo {
+            icmp_type,
+            icmp_code,
+            ..FlowInfo::new(ts)
         };
-
-        unsafe {
-            FLOW_INFO_TABLE
-                .insert(&key, &data, 0)
-                .expect("Error inserting flow info");
+        if unsafe { FLOW_INFO_TABLE.insert(&key, &info, 0) }.is_err() {
+            debug!(&ctx, "error inserting flow info");
+            return Ok(xdp_action::XDP_PASS);
         }
 
         debug!(
             &ctx,
-            "new flow {} {}:{} -> {}:{}",
+            "new flow vlan {} {} {:i}:{} -> {:i}:{}",
+            vlan_id,
             proto as u8,
             source_addr,
             source_port,
             dest_addr,
             dest_port
         );
+
+        match unsafe { FLOW_INFO_TABLE.get_ptr_mut(&key) } {
+            Some(data_ptr) => (key, DIR_FWD, data_ptr),
+            None => return Ok(xdp_action::XDP_PASS),
+        }
+    };
+
+    // closed flows were already reported, they are removed by the userspace sweeper
+    if unsafe { (*data_ptr).is_closed() } {
+        return Ok(xdp_action::XDP_PASS);
     }
+    // time since the previous packet of the flow, accounted or not
+    let idle = ts - unsafe { (*data_ptr).last_seen_ts };
+    unsafe { (*data_ptr).last_seen_ts = ts };
+
+    let end_reason = if tcp_flags & TCP_RST != 0 {
+        FLOW_END_RST
+    } else if tcp_flags & TCP_FIN != 0 {
+        FLOW_END_FIN
+    } else {
+        FLOW_ACTIVE
+    };
+
+    let config = CONFIG.get(0).copied().unwrap_or_default();
+
+    // only a window of packets is tracked, optionally restarting after the flow has been idle
+    if unsafe { (*data_ptr).num_packets() } >= config.window() {
+        if end_reason != FLOW_ACTIVE {
+            unsafe { (*data_ptr).end_reason = end_reason };
+            emit_event(&flow_key, direction, len, 0, data_ptr);
+            return Ok(xdp_action::XDP_PASS);
+        }
+
+        if config.cooldown_ns == 0 || idle < config.cooldown_ns {
+            return Ok(xdp_action::XDP_PASS);
+        }
+
+        debug!(&ctx, "restart flow window {:i}:{}", source_addr, source_port);
+        // the ICMP type and code of the flow stay those of its first packet
+        unsafe {
+            *data_ptr = FlowInfo {
+                icmp_type: (*data_ptr).icmp_type,
+                icmp_code: (*data_ptr).icmp_code,
+                ..FlowInfo::new(ts)
+            }
+        };
+    }
+
+    let delta = unsafe { (*data_ptr).update(direction, len, ts, l3.ip_options, tcp_flags) };
+    let num_packets = unsafe { (*data_ptr).num_packets() };
+
+    debug!(
+        &ctx,
+        "{} inc flow {} {:i}:{} -> {:i}:{} dir {} packets number {} len: {} delta: {}",
+        ts,
+        proto as u8,
+        source_addr,
+        source_port,
+        dest_addr,
+        dest_port,
+        direction,
+        num_packets,
+        len,
+        delta
+    );
+
+    // the last packet of the flow is always reported
+    if end_reason != FLOW_ACTIVE {
+        debug!(&ctx, "end flow {:i}:{} reason {}", source_addr, source_port, end_reason);
+        unsafe { (*data_ptr).end_reason = end_reason };
+    } else if !config.should_emit(num_packets) {
+        return Ok(xdp_action::XDP_PASS);
+    }
+
+    emit_event(&flow_key, direction, len, delta, data_ptr);
 
     Ok(xdp_action::XDP_PASS)
 }
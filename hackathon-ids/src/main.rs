@@ -1,18 +1,23 @@
 
-use std::collections::HashSet;
-use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::time::Duration;
 use anyhow::Context;
-use aya::maps::RingBuf;
+use aya::maps::{Array, HashMap, MapData, RingBuf};
 use aya::programs::{Xdp, XdpFlags};
 use aya::{include_bytes_aligned, Bpf};
 use aya_log::BpfLogger;
-use clap::Parser;
-use hackathon_ids_common::EventInfo;
+use clap::{Parser, ValueEnum};
+use hackathon_ids_common::{
+    BlockEntry, EventInfo, FlowConfig, FlowInfo, FlowKey, DIR_FWD,
+    EMIT_EVERY_NTH, EMIT_EVERY_PACKET, EMIT_WINDOW_END, FLOW_END_IDLE, VERDICT_DROP,
+};
 use log::{debug, info, warn};
 use ml::data::IDSItem;
+use ml::inference::Detector;
 use tokio::io::unix::AsyncFd;
 use tokio::signal;
 use tokio::sync::mpsc;
+use tokio::time::Instant;
 use tokio_util::sync::CancellationToken;
 
 
@@ -22,6 +27,129 @@ use burn::backend::NdArray;
 struct Opt {
     #[clap(short, long, default_value = "eth0")]
     iface: String,
+    /// Seconds a detected source stays in the blocklist, 0 blocks it forever
+    #[clap(long, default_value = "300")]
+    block_secs: u64,
+    /// Attack score (1 - probability of benign) from which a flow is reported and its source blocked
+    #[clap(long, default_value = "0.5")]
+    threshold: f32,
+    /// Maximum number of flows scored in a single inference batch
+    #[clap(long, default_value = "64")]
+    batch_size: usize,
+    /// Maximum milliseconds an event waits for its batch to fill up
+    #[clap(long, default_value = "5")]
+    batch_latency_ms: u64,
+    /// Number of packets tracked per flow window
+    #[clap(long, default_value = "10")]
+    window: u32,
+    /// When the XDP program reports a flow to the detector
+    #[clap(long, value_enum, default_value_t = EmitMode::Every)]
+    emit: EmitMode,
+    /// Cadence used with `--emit nth`
+    #[clap(long, default_value = "1")]
+    emit_every: u32,
+    /// Idle seconds after a full window before tracking the flow again, 0 never resumes
+    #[clap(long, default_value = "0")]
+    cooldown_secs: u64,
+    /// Seconds without packets after which a flow is exported and removed
+    #[clap(long, default_value = "120")]
+    idle_timeout_secs: u64,
+    /// Seconds between two sweeps of the flow table
+    #[clap(long, default_value = "10")]
+    sweep_interval_secs: u64,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum EmitMode {
+    /// Every tracked packet
+    Every,
+    /// Every `--emit-every` packets
+    Nth,
+    /// Only when the window is complete
+    WindowEnd,
+}
+
+impl Opt {
+    fn flow_config(&self) -> FlowConfig {
+        FlowConfig {
+            window: self.window,
+            emit_mode: match self.emit {
+                EmitMode::Every => EMIT_EVERY_PACKET,
+                EmitMode::Nth => EMIT_EVERY_NTH,
+                EmitMode::WindowEnd => EMIT_WINDOW_END,
+            },
+            emit_every: self.emit_every,
+            _pad: 0,
+            cooldown_ns: self.cooldown_secs.saturating_mul(1_000_000_000),
+        }
+    }
+}
+
+// same clock as bpf_ktime_get_ns, so expiry timestamps can be compared in the kernel
+fn ktime_now_ns() -> u64 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
+
+fn is_blocked(blocklist: &HashMap<MapData, [u8; 16], BlockEntry>, ip_src: [u8; 16]) -> bool {
+    blocklist
+        .get(&ip_src, 0)
+        .map(|entry| entry.is_active(ktime_now_ns()))
+        .unwrap_or(false)
+}
+
+fn block_source(blocklist: &mut HashMap<MapData, [u8; 16], BlockEntry>, ip_src: [u8; 16], block_secs: u64) {
+    let expires_at = if block_secs == 0 {
+        0
+    } else {
+        ktime_now_ns().saturating_add(block_secs.saturating_mul(1_000_000_000))
+    };
+    let entry = BlockEntry {
+        verdict: VERDICT_DROP,
+        _pad: 0,
+        expires_at,
+    };
+
+    if let Err(e) = blocklist.insert(ip_src, entry, 0) {
+        warn!("failed to block {}: {}", Ipv6Addr::from(ip_src).to_canonical(), e);
+    }
+}
+
+// removes closed and idle flows from the table, returning the final events of the idle ones
+// (closed flows were already reported by the XDP program)
+fn sweep_flows(flows: &mut HashMap<MapData, FlowKey, FlowInfo>, idle_timeout_ns: u64) -> Vec<EventInfo> {
+    let now = ktime_now_ns();
+    let mut expired = Vec::new();
+
+    for (key, mut flow) in flows.iter().filter_map(Result::ok) {
+        if flow.is_closed() {
+            expired.push((key, None));
+        } else if now.saturating_sub(flow.last_seen_ts) > idle_timeout_ns {
+            flow.end_reason = FLOW_END_IDLE;
+            let event = EventInfo {
+                key,
+                direction: DIR_FWD,
+                len: 0,
+                iat: 0,
+                flow,
+            };
+            expired.push((key, Some(event)));
+        }
+    }
+
+    expired
+        .into_iter()
+        .filter_map(|(key, event)| {
+            if let Err(e) = flows.remove(&key) {
+                debug!("failed to remove flow {:?}: {}", key, e);
+            }
+            event
+        })
+        .collect()
 }
 
 #[tokio::main]
@@ -57,16 +185,34 @@ async fn main() -> Result<(), anyhow::Error> {
         // This can happen if you remove all log statements from your eBPF program.
         warn!("failed to initialize eBPF logger: {}", e);
     }
+    let mut config: Array<_, FlowConfig> = Array::try_from(bpf.take_map("CONFIG").unwrap())?;
+    config.set(0, opt.flow_config(), 0)?;
+
     let program: &mut Xdp = bpf.program_mut("hackathon_ids").unwrap().try_into()?;
     program.load()?;
     program.attach(&opt.iface, XdpFlags::SKB_MODE)
         .context("failed to attach the XDP program with default flags - try changing XdpFlags::default() to XdpFlags::SKB_MODE")?;
 
+    let mut blocklist: HashMap<_, [u8; 16], BlockEntry> =
+        HashMap::try_from(bpf.take_map("BLOCKLIST").unwrap())?;
+    let block_secs = opt.block_secs;
+    let threshold = opt.threshold;
+
+    let batch_size = opt.batch_size.max(1);
+    let batch_latency = Duration::from_millis(opt.batch_latency_ms);
+
+    let mut flows: HashMap<_, FlowKey, FlowInfo> =
+        HashMap::try_from(bpf.take_map("FLOW_INFO_TABLE").unwrap())?;
+    let idle_timeout_ns = opt.idle_timeout_secs.saturating_mul(1_000_000_000);
+    let sweep_interval = Duration::from_secs(opt.sweep_interval_secs.max(1));
+
     let (tx, mut rx) = mpsc::channel(1024);
+    let tx_sweeper = tx.clone();
 
     let cancel_i = CancellationToken::new();
     let cancel_task_1 = cancel_i.clone();
     let cancel_task_2 = cancel_i.clone();
+    let cancel_task_3 = cancel_i.clone();
 
     let task_1 = tokio::spawn(async move {
         let events: RingBuf<_> = bpf.take_map("EVENTS").unwrap().try_into().unwrap();
@@ -96,51 +242,72 @@ async fn main() -> Result<(), anyhow::Error> {
         }
     });
 
+    let task_3 = tokio::spawn(async move {
+        let mut interval = tokio::time::interval(sweep_interval);
+
+        loop {
+            tokio::select! {
+                _ = cancel_task_3.cancelled() => {
+                    break;
+                }
+                _ = interval.tick() => {
+                    for info in sweep_flows(&mut flows, idle_timeout_ns) {
+                        debug!("Idle flow exported {:?}", info);
+                        if tx_sweeper.send(info).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    });
+
     let task_2 = tokio::spawn(async move {
         type MyBackend = NdArray;
         //type MyAutodiffBackend = Autodiff<MyBackend>;
 
         let device = burn::backend::ndarray::NdArrayDevice::default();
         let artifact_dir = "./ml/guide.lock";
-
-        let mut attacks: HashSet<u32> =  HashSet::new();
-
-        /*let info = rx.recv().await.unwrap();
-        info.*/
-        
-        
+        let detector = Detector::<MyBackend>::new(artifact_dir, device);
 
         loop {
-            tokio::select! {
+            // wait for the first event, then fill the batch until it is full or the deadline expires
+            let first = tokio::select! {
               _ = cancel_task_2.cancelled() => {
                   break;
               }
-              Some(info) = rx.recv() => {
-                if attacks.get(&info.ip_src).is_none() { // is not blocked already
-                  
-                  let total_len = info.total_len as f32;
-                  let total_iat = info.total_iat as f32;
-                  let num_pkts = info.num_packets as f32;
-                  let len_mean = total_len / num_pkts;
-
-                  let item= IDSItem {
-                    dst_port: info.port_dst,
-                    total_length_bwd_packet: total_len,
-                    bwd_iat_total: total_iat,
-                    bwd_iat_mean: total_iat / num_pkts,
-                    bwd_packet_length_mean: len_mean,
-                    bwd_packet_length_std: (info.len as f32 - len_mean).abs() / num_pkts,
-                    label: "".to_string() };
-
-                  debug!("Received {item:?}");
-                  let output = ml::inference::infer::<MyBackend>(artifact_dir, device, item);
-                  if output == 1 {
-                    info!("Detected an attack from {}:{} to {}:{} ", Ipv4Addr::from(info.ip_src), info.port_src, Ipv4Addr::from(info.ip_dst), info.port_dst);
-                    attacks.insert(info.ip_src);
-                    
-                  }
+              Some(info) = rx.recv() => info,
+            };
+
+            let mut events = vec![first];
+            let deadline = Instant::now() + batch_latency;
+            while events.len() < batch_size {
+                match tokio::time::timeout_at(deadline, rx.recv()).await {
+                    Ok(Some(info)) => events.push(info),
+                    _ => break,
+                }
+            }
+
+            events.retain(|info| !is_blocked(&blocklist, info.key.ip_src)); // is not blocked already
+            if events.is_empty() {
+                continue;
+            }
+
+            let items: Vec<IDSItem> = events.iter().map(IDSItem::from).collect();
+            debug!("Scoring a batch of {} flows", items.len());
+            let predictions = detector.predict_batch(items);
+
+            for (info, prediction) in events.iter().zip(predictions) {
+                let key = &info.key;
+                if prediction.exceeds(threshold) && !is_blocked(&blocklist, key.ip_src) {
+                    let score = prediction.attack_score();
+                    if key.is_icmp() {
+                        info!("Detected {} (probability {:.3}, attack score {:.3}) from {} to {} icmp {}/{} vlan {}", prediction.attack, prediction.attack_probability, score, key.src_addr(), key.dst_addr(), info.flow.icmp_type, info.flow.icmp_code, key.vlan_id);
+                    } else {
+                        info!("Detected {} (probability {:.3}, attack score {:.3}) from {}:{} to {}:{} vlan {}", prediction.attack, prediction.attack_probability, score, key.src_addr(), key.port_src, key.dst_addr(), key.port_dst, key.vlan_id);
+                    }
+                    block_source(&mut blocklist, key.ip_src, block_secs);
                 }
-              }
             }
         }
     });
@@ -152,6 +319,7 @@ async fn main() -> Result<(), anyhow::Error> {
     info!("Exiting...");
     task_1.await?;
     task_2.await?;
+    task_3.await?;
 
     Ok(())
 }
@@ -0,0 +1,98 @@
+use crate::{data::IDSItem, labels::LabelVocab};
+use burn::{data::dataset::Dataset, prelude::*};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use std::collections::BTreeMap;
+
+/// How training compensates for rare classes, recorded in `config.json`
+#[derive(Config, Debug, Copy, PartialEq, Eq)]
+pub enum ClassBalance {
+    /// Plain cross entropy over the dataset as it is
+    None,
+    /// Cross entropy weighted by the inverse frequency of each class
+    Weighted,
+    /// Every class resampled with replacement up to the size of the largest one
+    Oversample,
+    /// Every class sampled down to the size of the smallest one
+    Undersample,
+}
+
+/// Weight of each class of the vocabulary, `n / (classes * n_class)`, so that every class
+/// contributes the same to the loss
+pub fn class_weights<D: Dataset<IDSItem>>(dataset: &D, labels: &LabelVocab) -> Vec<f32> {
+    let mut counts = vec![0usize; labels.len()];
+    for item in dataset.iter() {
+        if let Some(class) = labels.index(&item.label) {
+            counts[class] += 1;
+        }
+    }
+
+    let total: usize = counts.iter().sum();
+    counts
+        .iter()
+        .map(|&count| {
+            if count > 0 {
+                total as f32 / (labels.len() * count) as f32
+            } else {
+                1.0
+            }
+        })
+        .collect()
+}
+
+/// Dataset seen through a list of indices, drawn once per training run so that every class
+/// has the same number of items
+pub struct ResampledDataset<D> {
+    dataset: D,
+    indices: Vec<usize>,
+}
+
+impl<D: Dataset<IDSItem>> ResampledDataset<D> {
+    pub fn new(dataset: D, balance: ClassBalance, seed: u64) -> Self {
+        let mut by_label: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+        for (index, item) in dataset.iter().enumerate() {
+            by_label.entry(item.label).or_default().push(index);
+        }
+
+        let sizes = by_label.values().map(Vec::len);
+        let target = match balance {
+            ClassBalance::Oversample => sizes.max(),
+            ClassBalance::Undersample => sizes.min(),
+            // every item once
+            ClassBalance::None | ClassBalance::Weighted => None,
+        };
+
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut indices = Vec::new();
+        for mut class in by_label.into_values() {
+            match target {
+                Some(target) if target > class.len() => {
+                    let extra: Vec<usize> = (class.len()..target)
+                        .map(|_| class[rng.gen_range(0..class.len())])
+                        .collect();
+                    class.extend(extra);
+                }
+                Some(target) => {
+                    class.shuffle(&mut rng);
+                    class.truncate(target);
+                }
+                None => {}
+            }
+            indices.extend(class);
+        }
+        indices.sort_unstable();
+
+        Self { dataset, indices }
+    }
+}
+
+impl<D: Dataset<IDSItem>> Dataset<IDSItem> for ResampledDataset<D> {
+    fn get(&self, index: usize) -> Option<IDSItem> {
+        self.indices
+            .get(index)
+            .and_then(|&index| self.dataset.get(index))
+    }
+
+    fn len(&self) -> usize {
+        self.indices.len()
+    }
+}
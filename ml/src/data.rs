@@ -7,6 +7,26 @@ use serde::Deserialize;
 pub struct IDSItem {
     #[serde(rename = "Dst Port")]
     pub dst_port: u16,
+    #[serde(rename = "Total Fwd Packet", default)]
+    pub total_fwd_packets: f32,
+    #[serde(rename = "Total Bwd packets", default)]
+    pub total_bwd_packets: f32,
+    #[serde(rename = "Total Length of Fwd Packet", default)]
+    pub total_length_fwd_packet: f32,
+    #[serde(rename = "Fwd IAT Total", default)]
+    pub fwd_iat_total: f32,
+    #[serde(rename = "Fwd IAT Mean", default)]
+    pub fwd_iat_mean: f32,
+    #[serde(rename = "Fwd IAT Std", default)]
+    pub fwd_iat_std: f32,
+    #[serde(rename = "Fwd Packet Length Mean", default)]
+    pub fwd_packet_length_mean: f32,
+    #[serde(rename = "Fwd Packet Length Std", default)]
+    pub fwd_packet_length_std: f32,
+    #[serde(rename = "Fwd Packet Length Min", default)]
+    pub fwd_packet_length_min: f32,
+    #[serde(rename = "Fwd Packet Length Max", default)]
+    pub fwd_packet_length_max: f32,
     #[serde(rename = "Total Length of Bwd Packet")]
     pub total_length_bwd_packet: f32,
     #[serde(rename = "Bwd IAT Total")]
@@ -17,7 +37,29 @@ pub struct IDSItem {
     pub bwd_packet_length_mean: f32,
     #[serde(rename = "Bwd Packet Length Std")]
     pub bwd_packet_length_std: f32,
-    #[serde(rename = "Label")]
+    #[serde(rename = "Bwd Packet Length Min", default)]
+    pub bwd_packet_length_min: f32,
+    #[serde(rename = "Bwd Packet Length Max", default)]
+    pub bwd_packet_length_max: f32,
+    #[serde(rename = "Bwd IAT Std", default)]
+    pub bwd_iat_std: f32,
+    #[serde(rename = "FIN Flag Count", default)]
+    pub fin_flag_count: Option<f32>,
+    #[serde(rename = "SYN Flag Count", default)]
+    pub syn_flag_count: Option<f32>,
+    #[serde(rename = "RST Flag Count", default)]
+    pub rst_flag_count: Option<f32>,
+    #[serde(rename = "PSH Flag Count", default)]
+    pub psh_flag_count: Option<f32>,
+    #[serde(rename = "ACK Flag Count", default)]
+    pub ack_flag_count: Option<f32>,
+    #[serde(rename = "URG Flag Count", default)]
+    pub urg_flag_count: Option<f32>,
+    // not in CIC-IDS2017, packets carrying IPv4 options or IPv6 extension headers
+    #[serde(rename = "IP Options Packets", default)]
+    pub ip_options_packets: f32,
+    // empty for flows to predict
+    #[serde(rename = "Label", default)]
     pub label: String,
 }
 
@@ -25,14 +67,64 @@ use burn::data::dataset::{Dataset, InMemDataset};
 
 use burn::{data::dataloader::batcher::Batcher, prelude::*};
 
+use crate::{features::FeatureSpec, labels::LabelVocab, normalization::Normalizer};
+
 #[derive(Clone)]
 pub struct IDSBatcher<B: Backend> {
     device: B::Device,
+    features: FeatureSpec,
+    labels: LabelVocab,
+    normalizer: Option<Normalizer>,
+    class_weights: Option<Vec<f32>>,
 }
 
 impl<B: Backend> IDSBatcher<B> {
-    pub fn new(device: B::Device) -> Self {
-        Self { device }
+    pub fn new(device: B::Device, features: FeatureSpec, labels: LabelVocab) -> Self {
+        Self {
+            device,
+            features,
+            labels,
+            normalizer: None,
+            class_weights: None,
+        }
+    }
+
+    /// Normalizes the features before batching them
+    pub fn with_normalizer(mut self, normalizer: Option<Normalizer>) -> Self {
+        self.normalizer = normalizer;
+        self
+    }
+
+    /// Weights the loss of each item by the weight of its class
+    pub fn with_class_weights(mut self, class_weights: Option<Vec<f32>>) -> Self {
+        self.class_weights = class_weights;
+        self
+    }
+
+    fn values(&self, item: &IDSItem) -> Vec<f32> {
+        let mut values = self.features.values(item);
+        if let Some(normalizer) = &self.normalizer {
+            normalizer.apply(&mut values);
+        }
+        values
+    }
+
+    /// Input tensor of unlabeled items, as used for inference
+    pub fn flows(&self, items: &[IDSItem]) -> Tensor<B, 2> {
+        let flows = items
+            .iter()
+            .map(|item| Data::new(self.values(item), Shape::new([1, self.features.len()])))
+            .map(|data| Tensor::<B, 2>::from_data(data.convert(), &self.device))
+            .collect();
+
+        Tensor::cat(flows, 0).to_device(&self.device)
+    }
+
+    fn target(&self, item: &IDSItem) -> i64 {
+        self.labels
+            .index(&item.label)
+            .unwrap_or_else(|| panic!("Label {:?} should be in the vocabulary", item.label))
+            as i64
     }
 }
 
@@ -40,38 +132,40 @@ impl<B: Backend> IDSBatcher<B> {
 pub struct IDSBatch<B: Backend> {
     pub flows: Tensor<B, 2>,
     pub targets: Tensor<B, 1, Int>,
+    /// Loss weight of each item, when training with class weights
+    pub weights: Option<Tensor<B, 1>>,
 }
 
 impl<B: Backend> Batcher<IDSItem, IDSBatch<B>> for IDSBatcher<B> {
     fn batch(&self, items: Vec<IDSItem>) -> IDSBatch<B> {
-        let flows = items
-            .iter()
-            .map(|item| {
-                Data::<f32, 2>::from([[
-                    item.total_length_bwd_packet as f32,
-                    item.bwd_iat_total as f32,
-                    item.bwd_iat_mean as f32,
-                    item.bwd_packet_length_mean as f32,
-                    item.bwd_packet_length_std as f32,
-                ]])
-            })
-            .map(|data| Tensor::<B, 2>::from_data(data.convert(), &self.device))
-            .collect();
+        let flows = self.flows(&items);
 
         let targets = items
             .iter()
             .map(|item| {
                 Tensor::<B, 1, Int>::from_data(
-                    Data::from([(if item.label == "BENIGN" { 0 } else { 1 } as i64).elem()]),
+                    Data::from([self.target(item).elem()]),
                     &self.device,
                 )
             })
             .collect();
 
-        let flows = Tensor::cat(flows, 0).to_device(&self.device);
         let targets = Tensor::cat(targets, 0).to_device(&self.device);
 
-        IDSBatch { flows, targets }
+        let weights = self.class_weights.as_ref().map(|class_weights| {
+            let weights: Vec<f32> = items
+                .iter()
+                .map(|item| class_weights[self.target(item) as usize])
+                .collect();
+            let data = Data::new(weights, Shape::new([items.len()]));
+            Tensor::<B, 1>::from_data(data.convert(), &self.device)
+        });
+
+        IDSBatch {
+            flows,
+            targets,
+            weights,
+        }
     }
 }
 
@@ -80,25 +174,10 @@ pub struct IDSDataset {
 }
 
 impl IDSDataset {
-
-    pub fn train() -> Self {
-        // TODO: fix hardcoded paths
-        IDSDataset {
-            dataset: InMemDataset::from_csv(
-                "./ml/data/train.csv",
-                ReaderBuilder::new().has_headers(true),
-            )
-            .unwrap(),
-        }
-    }
-
-    pub fn test() -> Self {
+    pub fn from_csv(path: &str) -> Self {
         IDSDataset {
-            dataset: InMemDataset::from_csv(
-                "./ml/data/test.csv",
-                ReaderBuilder::new().has_headers(true),
-            )
-            .unwrap(),
+            dataset: InMemDataset::from_csv(path, ReaderBuilder::new().has_headers(true))
+                .unwrap_or_else(|err| panic!("Dataset {path} should be readable: {err}")),
         }
     }
 }
@@ -0,0 +1,201 @@
+use crate::{data::IDSItem, inference::Classifier, labels::BENIGN};
+use burn::{data::dataset::Dataset, prelude::*};
+
+/// Precision, recall and F1 of one class
+#[derive(Config, Debug)]
+pub struct ClassReport {
+    pub label: String,
+    pub precision: f64,
+    pub recall: f64,
+    pub f1: f64,
+    /// Number of flows of the class in the dataset
+    pub support: usize,
+}
+
+/// Benign vs attack, ranking flows by attack score
+#[derive(Config, Debug)]
+pub struct AttackReport {
+    pub roc_auc: f64,
+    pub pr_auc: f64,
+}
+
+/// Quality of a model over a labeled dataset
+#[derive(Config, Debug)]
+pub struct EvaluationReport {
+    pub labels: Vec<String>,
+    /// Rows are the true classes, columns the predicted ones
+    pub confusion: Vec<Vec<usize>>,
+    pub classes: Vec<ClassReport>,
+    pub accuracy: f64,
+    pub macro_f1: f64,
+    pub attack: AttackReport,
+    /// Flows whose label is not in the vocabulary, e.g. attacks of a day left out of training.
+    /// They are only counted in the benign vs attack AUCs.
+    pub unknown: usize,
+}
+
+// a / b, 0 when there is nothing to divide
+fn ratio(a: f64, b: f64) -> f64 {
+    if b > 0.0 {
+        a / b
+    } else {
+        0.0
+    }
+}
+
+// scores sorted from the most to the least likely attack, grouped by equal score so that
+// ties do not depend on the dataset order
+fn ranked_groups(scored: &[(f32, bool)]) -> Vec<(usize, usize)> {
+    let mut sorted = scored.to_vec();
+    sorted.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    let mut groups: Vec<(usize, usize)> = Vec::new();
+    let mut last = None;
+    for (score, attack) in sorted {
+        if last != Some(score) {
+            groups.push((0, 0));
+            last = Some(score);
+        }
+        let group = groups.last_mut().unwrap();
+        if attack {
+            group.0 += 1;
+        } else {
+            group.1 += 1;
+        }
+    }
+    groups
+}
+
+/// Area under the ROC curve of (attack score, is attack) pairs
+pub fn roc_auc(scored: &[(f32, bool)]) -> f64 {
+    let positives = scored.iter().filter(|(_, attack)| *attack).count() as f64;
+    let negatives = scored.len() as f64 - positives;
+
+    // trapezoids between the points of each score threshold
+    let (mut tp, mut fp, mut area) = (0.0, 0.0, 0.0);
+    for (group_tp, group_fp) in ranked_groups(scored) {
+        let (next_tp, next_fp) = (tp + group_tp as f64, fp + group_fp as f64);
+        area += (next_fp - fp) * (tp + next_tp) / 2.0;
+        (tp, fp) = (next_tp, next_fp);
+    }
+    ratio(area, positives * negatives)
+}
+
+/// Area under the precision-recall curve (average precision) of (attack score, is attack) pairs
+pub fn pr_auc(scored: &[(f32, bool)]) -> f64 {
+    let positives = scored.iter().filter(|(_, attack)| *attack).count() as f64;
+
+    let (mut tp, mut fp, mut area) = (0.0, 0.0, 0.0);
+    for (group_tp, group_fp) in ranked_groups(scored) {
+        tp += group_tp as f64;
+        fp += group_fp as f64;
+        area += ratio(group_tp as f64, positives) * ratio(tp, tp + fp);
+    }
+    area
+}
+
+impl EvaluationReport {
+    /// Scores every item of the dataset, in batches of `batch_size`
+    pub fn evaluate<C: Classifier + ?Sized, D: Dataset<IDSItem>>(
+        classifier: &C,
+        dataset: &D,
+        batch_size: usize,
+    ) -> Self {
+        let labels = classifier.labels();
+        let num_classes = labels.len();
+        let mut confusion = vec![vec![0; num_classes]; num_classes];
+        let mut scored = Vec::with_capacity(dataset.len());
+        let mut unknown = 0;
+
+        let items: Vec<IDSItem> = dataset.iter().collect();
+        for chunk in items.chunks(batch_size.max(1)) {
+            let predictions = classifier.predict_batch(chunk.to_vec());
+            for (item, prediction) in chunk.iter().zip(predictions) {
+                match labels.index(&item.label) {
+                    Some(class) => confusion[class][prediction.class] += 1,
+                    None => unknown += 1,
+                }
+                scored.push((prediction.attack_score(), item.label != BENIGN));
+            }
+        }
+
+        Self::from_confusion(labels.labels.clone(), confusion, &scored, unknown)
+    }
+
+    fn from_confusion(
+        labels: Vec<String>,
+        confusion: Vec<Vec<usize>>,
+        scored: &[(f32, bool)],
+        unknown: usize,
+    ) -> Self {
+        let total: usize = confusion.iter().flatten().sum();
+        let correct: usize = (0..labels.len()).map(|class| confusion[class][class]).sum();
+
+        let classes: Vec<ClassReport> = labels
+            .iter()
+            .enumerate()
+            .map(|(class, label)| {
+                let tp = confusion[class][class] as f64;
+                let support: usize = confusion[class].iter().sum();
+                let predicted: usize = confusion.iter().map(|row| row[class]).sum();
+
+                let precision = ratio(tp, predicted as f64);
+                let recall = ratio(tp, support as f64);
+                let f1 = ratio(2.0 * precision * recall, precision + recall);
+                ClassReport::new(label.clone(), precision, recall, f1, support)
+            })
+            .collect();
+
+        let macro_f1 = ratio(
+            classes.iter().map(|class| class.f1).sum(),
+            classes.len() as f64,
+        );
+
+        EvaluationReport::new(
+            labels,
+            confusion,
+            classes,
+            ratio(correct as f64, total as f64),
+            macro_f1,
+            AttackReport::new(roc_auc(scored), pr_auc(scored)),
+            unknown,
+        )
+    }
+
+    pub fn print(&self) {
+        println!(
+            "{:<4} {:<32} {:>9} {:>9} {:>9} {:>9}",
+            "", "class", "precision", "recall", "f1", "support"
+        );
+        for (class, report) in self.classes.iter().enumerate() {
+            println!(
+                "{:<4} {:<32} {:>9.4} {:>9.4} {:>9.4} {:>9}",
+                class, report.label, report.precision, report.recall, report.f1, report.support
+            );
+        }
+
+        println!();
+        println!("confusion matrix (rows are true classes, columns predicted ones)");
+        print!("{:<4}", "");
+        for class in 0..self.labels.len() {
+            print!(" {class:>9}");
+        }
+        println!();
+        for (class, row) in self.confusion.iter().enumerate() {
+            print!("{class:<4}");
+            for count in row {
+                print!(" {count:>9}");
+            }
+            println!();
+        }
+
+        println!();
+        println!("accuracy {:.4}", self.accuracy);
+        println!("macro f1 {:.4}", self.macro_f1);
+        println!("roc auc  {:.4}", self.attack.roc_auc);
+        println!("pr auc   {:.4}", self.attack.pr_auc);
+        if self.unknown > 0 {
+            println!("{} flows with labels outside the vocabulary", self.unknown);
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use crate::data::IDSItem;
+use burn::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Flow features the model can be trained on, serialized with their CIC-IDS2017 column name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Feature {
+    #[serde(rename = "Dst Port")]
+    DstPort,
+    #[serde(rename = "Total Fwd Packet")]
+    TotalFwdPackets,
+    #[serde(rename = "Total Bwd packets")]
+    TotalBwdPackets,
+    #[serde(rename = "Total Length of Fwd Packet")]
+    TotalLengthFwdPacket,
+    #[serde(rename = "Total Length of Bwd Packet")]
+    TotalLengthBwdPacket,
+    #[serde(rename = "Fwd IAT Total")]
+    FwdIatTotal,
+    #[serde(rename = "Fwd IAT Mean")]
+    FwdIatMean,
+    #[serde(rename = "Fwd IAT Std")]
+    FwdIatStd,
+    #[serde(rename = "Fwd Packet Length Mean")]
+    FwdPacketLengthMean,
+    #[serde(rename = "Fwd Packet Length Std")]
+    FwdPacketLengthStd,
+    #[serde(rename = "Fwd Packet Length Min")]
+    FwdPacketLengthMin,
+    #[serde(rename = "Fwd Packet Length Max")]
+    FwdPacketLengthMax,
+    #[serde(rename = "Bwd IAT Total")]
+    BwdIatTotal,
+    #[serde(rename = "Bwd IAT Mean")]
+    BwdIatMean,
+    #[serde(rename = "Bwd IAT Std")]
+    BwdIatStd,
+    #[serde(rename = "Bwd Packet Length Mean")]
+    BwdPacketLengthMean,
+    #[serde(rename = "Bwd Packet Length Std")]
+    BwdPacketLengthStd,
+    #[serde(rename = "Bwd Packet Length Min")]
+    BwdPacketLengthMin,
+    #[serde(rename = "Bwd Packet Length Max")]
+    BwdPacketLengthMax,
+    #[serde(rename = "FIN Flag Count")]
+    FinFlagCount,
+    #[serde(rename = "SYN Flag Count")]
+    SynFlagCount,
+    #[serde(rename = "RST Flag Count")]
+    RstFlagCount,
+    #[serde(rename = "PSH Flag Count")]
+    PshFlagCount,
+    #[serde(rename = "ACK Flag Count")]
+    AckFlagCount,
+    #[serde(rename = "URG Flag Count")]
+    UrgFlagCount,
+    #[serde(rename = "IP Options Packets")]
+    IpOptionsPackets,
+}
+
+impl Feature {
+    /// Every feature, in dataset column order
+    pub const ALL: [Feature; 26] = [
+        Feature::DstPort,
+        Feature::TotalFwdPackets,
+        Feature::TotalBwdPackets,
+        Feature::TotalLengthFwdPacket,
+        Feature::TotalLengthBwdPacket,
+        Feature::FwdIatTotal,
+        Feature::FwdIatMean,
+        Feature::FwdIatStd,
+        Feature::FwdPacketLengthMean,
+        Feature::FwdPacketLengthStd,
+        Feature::FwdPacketLengthMin,
+        Feature::FwdPacketLengthMax,
+        Feature::BwdIatTotal,
+        Feature::BwdIatMean,
+        Feature::BwdIatStd,
+        Feature::BwdPacketLengthMean,
+        Feature::BwdPacketLengthStd,
+        Feature::BwdPacketLengthMin,
+        Feature::BwdPacketLengthMax,
+        Feature::FinFlagCount,
+        Feature::SynFlagCount,
+        Feature::RstFlagCount,
+        Feature::PshFlagCount,
+        Feature::AckFlagCount,
+        Feature::UrgFlagCount,
+        Feature::IpOptionsPackets,
+    ];
+
+    /// Name of the dataset column
+    pub fn column(&self) -> &'static str {
+        match self {
+            Feature::DstPort => "Dst Port",
+            Feature::TotalFwdPackets => "Total Fwd Packet",
+            Feature::TotalBwdPackets => "Total Bwd packets",
+            Feature::TotalLengthFwdPacket => "Total Length of Fwd Packet",
+            Feature::TotalLengthBwdPacket => "Total Length of Bwd Packet",
+            Feature::FwdIatTotal => "Fwd IAT Total",
+            Feature::FwdIatMean => "Fwd IAT Mean",
+            Feature::FwdIatStd => "Fwd IAT Std",
+            Feature::FwdPacketLengthMean => "Fwd Packet Length Mean",
+            Feature::FwdPacketLengthStd => "Fwd Packet Length Std",
+            Feature::FwdPacketLengthMin => "Fwd Packet Length Min",
+            Feature::FwdPacketLengthMax => "Fwd Packet Length Max",
+            Feature::BwdIatTotal => "Bwd IAT Total",
+            Feature::BwdIatMean => "Bwd IAT Mean",
+            Feature::BwdIatStd => "Bwd IAT Std",
+            Feature::BwdPacketLengthMean => "Bwd Packet Length Mean",
+            Feature::BwdPacketLengthStd => "Bwd Packet Length Std",
+            Feature::BwdPacketLengthMin => "Bwd Packet Length Min",
+            Feature::BwdPacketLengthMax => "Bwd Packet Length Max",
+            Feature::FinFlagCount => "FIN Flag Count",
+            Feature::SynFlagCount => "SYN Flag Count",
+            Feature::RstFlagCount => "RST Flag Count",
+            Feature::PshFlagCount => "PSH Flag Count",
+            Feature::AckFlagCount => "ACK Flag Count",
+            Feature::UrgFlagCount => "URG Flag Count",
+            Feature::IpOptionsPackets => "IP Options Packets",
+        }
+    }
+
+    pub fn value(&self, item: &IDSItem) -> f32 {
+        match self {
+            Feature::DstPort => item.dst_port as f32,
+            Feature::TotalFwdPackets => item.total_fwd_packets,
+            Feature::TotalBwdPackets => item.total_bwd_packets,
+            Feature::TotalLengthFwdPacket => item.total_length_fwd_packet,
+            Feature::TotalLengthBwdPacket => item.total_length_bwd_packet,
+            Feature::FwdIatTotal => item.fwd_iat_total,
+            Feature::FwdIatMean => item.fwd_iat_mean,
+            Feature::FwdIatStd => item.fwd_iat_std,
+            Feature::FwdPacketLengthMean => item.fwd_packet_length_mean,
+            Feature::FwdPacketLengthStd => item.fwd_packet_length_std,
+            Feature::FwdPacketLengthMin => item.fwd_packet_length_min,
+            Feature::FwdPacketLengthMax => item.fwd_packet_length_max,
+            Feature::BwdIatTotal => item.bwd_iat_total,
+            Feature::BwdIatMean => item.bwd_iat_mean,
+            Feature::BwdIatStd => item.bwd_iat_std,
+            Feature::BwdPacketLengthMean => item.bwd_packet_length_mean,
+            Feature::BwdPacketLengthStd => item.bwd_packet_length_std,
+            Feature::BwdPacketLengthMin => item.bwd_packet_length_min,
+            Feature::BwdPacketLengthMax => item.bwd_packet_length_max,
+            // flag counters are optional columns, missing ones count as 0
+            Feature::FinFlagCount => item.fin_flag_count.unwrap_or_default(),
+            Feature::SynFlagCount => item.syn_flag_count.unwrap_or_default(),
+            Feature::RstFlagCount => item.rst_flag_count.unwrap_or_default(),
+            Feature::PshFlagCount => item.psh_flag_count.unwrap_or_default(),
+            Feature::AckFlagCount => item.ack_flag_count.unwrap_or_default(),
+            Feature::UrgFlagCount => item.urg_flag_count.unwrap_or_default(),
+            Feature::IpOptionsPackets => item.ip_options_packets,
+        }
+    }
+}
+
+/// Ordered list of the model inputs, saved as `features.json` in the artifact directory
+#[derive(Config, Debug)]
+pub struct FeatureSpec {
+    pub features: Vec<Feature>,
+}
+
+impl Default for FeatureSpec {
+    // inputs of the models trained before the spec was saved with them
+    fn default() -> Self {
+        FeatureSpec::new(vec![
+            Feature::TotalLengthBwdPacket,
+            Feature::BwdIatTotal,
+            Feature::BwdIatMean,
+            Feature::BwdPacketLengthMean,
+            Feature::BwdPacketLengthStd,
+        ])
+    }
+}
+
+impl FeatureSpec {
+    /// Loads the spec of an artifact directory, falling back to the default one
+    pub fn load_or_default(artifact_dir: &str) -> Self {
+        FeatureSpec::load(format!("{artifact_dir}/features.json")).unwrap_or_default()
+    }
+
+    /// Every feature found in the CIC-IDS2017 CSVs
+    pub fn dataset() -> Self {
+        FeatureSpec::new(
+            Feature::ALL
+                .into_iter()
+                .filter(|feature| *feature != Feature::IpOptionsPackets)
+                .collect(),
+        )
+    }
+
+    pub fn len(&self) -> usize {
+        self.features.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.features.is_empty()
+    }
+
+    /// Input vector of an item, in spec order
+    pub fn values(&self, item: &IDSItem) -> Vec<f32> {
+        self.features
+            .iter()
+            .map(|feature| feature.value(item))
+            .collect()
+    }
+}
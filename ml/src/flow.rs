@@ -0,0 +1,92 @@
+// Conversion of the flow snapshots sent by the XDP program into dataset items, in the units
+// of the CIC-IDS2017 columns
+
+use crate::data::IDSItem;
+use hackathon_ids_common::{DirectionStats, EventInfo};
+
+// a / b, 0 when there is nothing to average
+fn mean(total: f32, count: f32) -> f32 {
+    if count > 0.0 {
+        total / count
+    } else {
+        0.0
+    }
+}
+
+// sample standard deviation (as in the dataset) from the sum and sum of squares of n values
+fn std_dev(sum: f64, sum_sq: f64, n: f64) -> f32 {
+    if n < 2.0 {
+        return 0.0;
+    }
+
+    let variance = (sum_sq - sum * sum / n) / (n - 1.0);
+    variance.max(0.0).sqrt() as f32
+}
+
+fn len_std(stats: &DirectionStats) -> f32 {
+    std_dev(
+        stats.total_len as f64,
+        stats.sum_sq_len as f64,
+        stats.num_packets as f64,
+    )
+}
+
+// the XDP program timestamps packets with bpf_ktime_get_ns, while the CIC-IDS2017 IAT
+// columns are in microseconds
+const NANOS_PER_MICRO: f64 = 1000.0;
+
+fn micros(nanos: u64) -> f32 {
+    (nanos as f64 / NANOS_PER_MICRO) as f32
+}
+
+// squares are already accumulated in microseconds by the XDP program
+fn iat_std_us(stats: &DirectionStats) -> f32 {
+    let gaps = stats.num_packets.saturating_sub(1) as f64;
+    std_dev(
+        stats.total_iat as f64 / NANOS_PER_MICRO,
+        stats.sum_sq_iat_us as f64,
+        gaps,
+    )
+}
+
+impl From<&EventInfo> for IDSItem {
+    fn from(info: &EventInfo) -> Self {
+        let fwd = &info.flow.fwd;
+        let bwd = &info.flow.bwd;
+
+        let fwd_pkts = fwd.num_packets as f32;
+        let bwd_pkts = bwd.num_packets as f32;
+
+        IDSItem {
+            // 0 for ICMP flows, as in the dataset
+            dst_port: info.key.port_dst,
+            total_fwd_packets: fwd_pkts,
+            total_bwd_packets: bwd_pkts,
+            total_length_fwd_packet: fwd.total_len as f32,
+            // IATs are gaps between consecutive packets, so there is one less than packets
+            fwd_iat_total: micros(fwd.total_iat),
+            fwd_iat_mean: mean(micros(fwd.total_iat), fwd_pkts - 1.0),
+            fwd_iat_std: iat_std_us(fwd),
+            fwd_packet_length_mean: mean(fwd.total_len as f32, fwd_pkts),
+            fwd_packet_length_std: len_std(fwd),
+            fwd_packet_length_min: fwd.min_len as f32,
+            fwd_packet_length_max: fwd.max_len as f32,
+            total_length_bwd_packet: bwd.total_len as f32,
+            bwd_iat_total: micros(bwd.total_iat),
+            bwd_iat_mean: mean(micros(bwd.total_iat), bwd_pkts - 1.0),
+            bwd_iat_std: iat_std_us(bwd),
+            bwd_packet_length_mean: mean(bwd.total_len as f32, bwd_pkts),
+            bwd_packet_length_std: len_std(bwd),
+            bwd_packet_length_min: bwd.min_len as f32,
+            bwd_packet_length_max: bwd.max_len as f32,
+            fin_flag_count: Some(info.flow.fin_count as f32),
+            syn_flag_count: Some(info.flow.syn_count as f32),
+            rst_flag_count: Some(info.flow.rst_count as f32),
+            psh_flag_count: Some(info.flow.psh_count as f32),
+            ack_flag_count: Some(info.flow.ack_count as f32),
+            urg_flag_count: Some(info.flow.urg_count as f32),
+            ip_options_packets: info.flow.ip_options_packets as f32,
+            label: "".to_string(),
+        }
+    }
+}
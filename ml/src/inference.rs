@@ -1,24 +1,144 @@
-use crate::{data::IDSBatcher, data::IDSItem, model::Model, training::TrainingConfig};
-use burn::{
-    data::dataloader::batcher::Batcher,
-    prelude::*,
-    record::{CompactRecorder, Recorder},
+use crate::{
+    data::IDSBatcher, data::IDSItem, features::FeatureSpec, labels::LabelVocab, model::Model,
+    normalization::Normalizer, training::TrainingConfig,
 };
+use burn::{prelude::*, tensor::activation::softmax};
 
-pub fn infer<B: Backend>(artifact_dir: &str, device: B::Device, item: IDSItem) -> i8 {
-    let config = TrainingConfig::load(format!("{artifact_dir}/config.json"))
-        .expect("Config should exist for the model");
-    let record = CompactRecorder::new()
-        .load(format!("{artifact_dir}/model").into(), &device)
-        .expect("Trained model should exist");
+/// Class probabilities of a flow
+#[derive(Debug, Clone, PartialEq)]
+pub struct Prediction {
+    /// Most likely class, its label and probability
+    pub class: usize,
+    pub label: String,
+    pub probability: f32,
+    /// Most likely attack type, even when the flow is more likely benign, and its probability.
+    /// Empty with a probability of 0 when the model only knows the benign class.
+    pub attack: String,
+    pub attack_probability: f32,
+    /// Probability of each class
+    pub probabilities: Vec<f32>,
+}
 
-    let model: Model<B> = config.model.init(&device).load_record(record);
+impl Prediction {
+    pub fn new(labels: &LabelVocab, probabilities: Vec<f32>) -> Self {
+        let (class, probability) = argmax(&probabilities);
+        let (attack, attack_probability) = if probabilities.len() < 2 {
+            (String::new(), 0.0)
+        } else {
+            let (attack, p) = argmax(&probabilities[1..]);
+            (labels.name(attack + 1).to_string(), p)
+        };
 
-    //let label = item.label.clone();
-    let batcher = IDSBatcher::new(device);
-    let batch = batcher.batch(vec![item]);
-    let output = model.forward(batch.flows);
-    let predicted = output.argmax(1).flatten::<1>(0, 1).into_scalar();
+        Prediction {
+            class,
+            label: labels.name(class).to_string(),
+            probability,
+            attack,
+            attack_probability,
+            probabilities,
+        }
+    }
 
-    predicted.elem()
-}
\ No newline at end of file
+    pub fn is_attack(&self) -> bool {
+        // the vocabulary always starts with BENIGN
+        self.class != 0
+    }
+
+    /// Probability of the flow not being benign
+    pub fn attack_score(&self) -> f32 {
+        self.probabilities.first().map_or(0.0, |benign| 1.0 - benign)
+    }
+
+    /// Whether the attack score reaches an alert threshold
+    pub fn exceeds(&self, threshold: f32) -> bool {
+        self.attack_score() >= threshold
+    }
+}
+
+// index and value of the highest probability
+fn argmax(probabilities: &[f32]) -> (usize, f32) {
+    probabilities
+        .iter()
+        .copied()
+        .enumerate()
+        .fold(
+            (0, f32::MIN),
+            |best, (class, p)| if p > best.1 { (class, p) } else { best },
+        )
+}
+
+/// Trained model loaded once from an artifact directory and kept in memory
+pub struct Detector<B: Backend> {
+    model: Model<B>,
+    batcher: IDSBatcher<B>,
+    labels: LabelVocab,
+}
+
+impl<B: Backend> Detector<B> {
+    pub fn new(artifact_dir: &str, device: B::Device) -> Self {
+        let config = TrainingConfig::load(format!("{artifact_dir}/config.json"))
+            .expect("Config should exist for the model");
+        let model: Model<B> = config.model.load(&format!("{artifact_dir}/model"), &device);
+        let labels = LabelVocab::load_or_default(artifact_dir);
+        let batcher = IDSBatcher::new(
+            device,
+            FeatureSpec::load_or_default(artifact_dir),
+            labels.clone(),
+        )
+        .with_normalizer(Normalizer::load_optional(artifact_dir));
+
+        Self {
+            model,
+            batcher,
+            labels,
+        }
+    }
+
+    /// Classes of the model outputs
+    pub fn labels(&self) -> &LabelVocab {
+        &self.labels
+    }
+
+    pub fn predict(&self, item: IDSItem) -> Prediction {
+        self.predict_batch(vec![item]).remove(0)
+    }
+
+    pub fn predict_batch(&self, items: Vec<IDSItem>) -> Vec<Prediction> {
+        if items.is_empty() {
+            return Vec::new();
+        }
+
+        // items from the daemon have no label, so only the inputs are built
+        let flows = self.batcher.flows(&items);
+        let output = softmax(self.model.forward(flows), 1);
+        let [_, num_classes] = output.dims();
+        let probabilities: Vec<f32> = output.into_data().convert().value;
+
+        probabilities
+            .chunks(num_classes)
+            .map(|row| Prediction::new(&self.labels, row.to_vec()))
+            .collect()
+    }
+}
+
+/// Model scoring flows, either the `Detector` MLP or the trees of `TreeClassifier`
+pub trait Classifier {
+    /// Classes of the model outputs
+    fn labels(&self) -> &LabelVocab;
+
+    fn predict_batch(&self, items: Vec<IDSItem>) -> Vec<Prediction>;
+}
+
+impl<B: Backend> Classifier for Detector<B> {
+    fn labels(&self) -> &LabelVocab {
+        Detector::labels(self)
+    }
+
+    fn predict_batch(&self, items: Vec<IDSItem>) -> Vec<Prediction> {
+        Detector::predict_batch(self, items)
+    }
+}
+
+pub fn infer<B: Backend>(artifact_dir: &str, device: B::Device, item: IDSItem) -> Prediction {
+    Detector::<B>::new(artifact_dir, device).predict(item)
+}
@@ -0,0 +1,61 @@
+use crate::data::IDSItem;
+use burn::{data::dataset::Dataset, prelude::*};
+
+pub const BENIGN: &str = "BENIGN";
+const ATTACK: &str = "ATTACK";
+
+/// Class names of the model outputs, saved as `labels.json` in the artifact directory.
+/// `BENIGN` is always class 0.
+#[derive(Config, Debug)]
+pub struct LabelVocab {
+    pub labels: Vec<String>,
+}
+
+impl Default for LabelVocab {
+    // classes of the models trained before the vocabulary was saved with them
+    fn default() -> Self {
+        LabelVocab::new(vec![BENIGN.to_string(), ATTACK.to_string()])
+    }
+}
+
+impl LabelVocab {
+    /// Collects the labels found in the dataset, attacks sorted by name after `BENIGN`
+    pub fn fit<D: Dataset<IDSItem>>(dataset: &D) -> Self {
+        let mut attacks: Vec<String> = dataset
+            .iter()
+            .map(|item| item.label)
+            .filter(|label| label != BENIGN)
+            .collect();
+        attacks.sort();
+        attacks.dedup();
+
+        let mut labels = vec![BENIGN.to_string()];
+        labels.extend(attacks);
+        LabelVocab::new(labels)
+    }
+
+    /// Loads the vocabulary of an artifact directory, falling back to benign/attack
+    pub fn load_or_default(artifact_dir: &str) -> Self {
+        LabelVocab::load(format!("{artifact_dir}/labels.json")).unwrap_or_default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    /// Class of a dataset label, binary vocabularies map every attack to class 1
+    pub fn index(&self, label: &str) -> Option<usize> {
+        self.labels
+            .iter()
+            .position(|known| known == label)
+            .or_else(|| (self.labels == [BENIGN, ATTACK] && label != BENIGN).then_some(1))
+    }
+
+    pub fn name(&self, class: usize) -> &str {
+        &self.labels[class]
+    }
+}
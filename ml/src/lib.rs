@@ -1,4 +1,13 @@
+pub mod balance;
 pub mod data;
+pub mod features;
+pub mod labels;
+pub mod flow;
 pub mod model;
+pub mod normalization;
+pub mod optimization;
+pub mod preparation;
 pub mod training;
-pub mod inference;
\ No newline at end of file
+pub mod inference;
+pub mod evaluation;
+pub mod tree;
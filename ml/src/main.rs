@@ -1,23 +1,460 @@
 use std::error::Error;
 
-use ml::model::ModelConfig;
-use ml::training::TrainingConfig;
+use ml::balance::ClassBalance;
+use ml::data::IDSDataset;
+use ml::evaluation::EvaluationReport;
+use ml::features::FeatureSpec;
+use ml::inference::{Classifier, Detector};
+use ml::labels::LabelVocab;
+use ml::model::{Activation, ModelConfig, Norm};
+use ml::optimization::{LrDecay, LrScheduleConfig, OptimizerKind};
+use ml::preparation::PrepareConfig;
+use ml::training::{SelectionMetric, TrainingConfig};
+use ml::tree::{ForestConfig, TreeClassifier};
 
-use burn::{backend::{Autodiff, NdArray}, optim::AdamConfig};
+use burn::{
+    backend::{Autodiff, NdArray},
+    config::Config,
+    data::dataset::Dataset,
+    optim::{decay::WeightDecayConfig, momentum::MomentumConfig, AdamConfig, AdamWConfig, SgdConfig},
+};
+use clap::{Parser, ValueEnum};
 
+type MyBackend = NdArray;
+type MyAutodiffBackend = Autodiff<MyBackend>;
 
-fn main() -> Result<(), Box<dyn Error>> {
-    type MyBackend = NdArray;
-    type MyAutodiffBackend = Autodiff<MyBackend>;
+#[derive(Debug, Parser)]
+struct Options {
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Parser)]
+enum Command {
+    /// Clean the original CIC-IDS2017 CSVs and split them into train, validation and test sets
+    Prepare(PrepareOptions),
+    /// Train a model and store it in an artifact directory
+    Train(TrainOptions),
+    /// Train a decision tree or a random forest with integer thresholds
+    TrainTree(TrainTreeOptions),
+    /// Score a labeled CSV with a trained model
+    Eval(EvalOptions),
+    /// Print the predicted class and attack score of every row of a CSV
+    Predict(PredictOptions),
+}
+
+#[derive(Debug, Parser)]
+struct PrepareOptions {
+    /// Original per-day CSVs
+    #[clap(required = true)]
+    inputs: Vec<String>,
+    /// Directory where train.csv, valid.csv and test.csv are written
+    #[clap(long, default_value = "./ml/data")]
+    output_dir: String,
+    /// Feature spec (features.json) of the columns to keep, every dataset feature by default
+    #[clap(long)]
+    features: Option<String>,
+    #[clap(long, default_value = "0.7")]
+    train_ratio: f64,
+    #[clap(long, default_value = "0.15")]
+    valid_ratio: f64,
+    #[clap(long, default_value = "42")]
+    seed: u64,
+}
+
+#[derive(Debug, Parser)]
+struct TrainOptions {
+    /// Directory where the model, its config and the fitted artifacts are stored
+    #[clap(long, default_value = "./ml/guide")]
+    artifact_dir: String,
+    /// Labeled CSV the model is trained on
+    #[clap(long, default_value = "./ml/data/train.csv")]
+    train_data: String,
+    /// Labeled CSV the model is validated on after each epoch
+    #[clap(long, default_value = "./ml/data/valid.csv")]
+    valid_data: String,
+    /// Feature spec (features.json) of the model inputs, the default spec otherwise
+    #[clap(long)]
+    features: Option<String>,
+    #[clap(long, default_value = "3")]
+    epochs: usize,
+    #[clap(long, default_value = "64")]
+    batch_size: usize,
+    #[clap(long, default_value = "1e-4")]
+    learning_rate: f64,
+    /// Width of the hidden layer
+    #[clap(long, default_value = "512")]
+    hidden_size: usize,
+    /// Widths of several hidden layers, e.g. 256,128,64, instead of a single `--hidden-size` one
+    #[clap(long, value_delimiter = ',')]
+    hidden_sizes: Vec<usize>,
+    #[clap(long, value_enum, default_value_t = ActivationChoice::Relu)]
+    activation: ActivationChoice,
+    /// Normalization after each hidden linear layer
+    #[clap(long, value_enum, default_value_t = NormChoice::None)]
+    norm: NormChoice,
+    #[clap(long, default_value = "0.5")]
+    dropout: f64,
+    /// Dropout of each hidden layer, e.g. 0.3,0.2, `--dropout` for the layers not listed
+    #[clap(long, value_delimiter = ',')]
+    dropouts: Vec<f64>,
+    #[clap(long, default_value = "42")]
+    seed: u64,
+    /// Train on the raw features instead of fitting a normalizer on the training set
+    #[clap(long)]
+    no_normalize: bool,
+    /// Normalize log1p of the features, for heavy tailed counters
+    #[clap(long, conflicts_with = "no_normalize")]
+    log1p: bool,
+    /// How rare classes are compensated for
+    #[clap(long, value_enum, default_value_t = Balance::None)]
+    balance: Balance,
+    /// Validation metric of the checkpoint kept as the model
+    #[clap(long, value_enum, default_value_t = Selection::Loss)]
+    selection_metric: Selection,
+    /// Stop after this many epochs without improvement of the selection metric
+    #[clap(long)]
+    patience: Option<usize>,
+    #[clap(long, value_enum, default_value_t = OptimizerChoice::Adam)]
+    optimizer: OptimizerChoice,
+    /// Weight decay of the optimizer
+    #[clap(long)]
+    weight_decay: Option<f64>,
+    /// Momentum of SGD
+    #[clap(long, default_value = "0.9")]
+    momentum: f64,
+    /// Steps (batches) of linear learning rate warmup
+    #[clap(long, default_value = "0")]
+    warmup_steps: usize,
+    #[clap(long, value_enum, default_value_t = Schedule::Constant)]
+    schedule: Schedule,
+    /// Steps of the cosine annealing, or between two step decays
+    #[clap(long, default_value = "1000")]
+    decay_steps: usize,
+    /// Final learning rate of the cosine annealing
+    #[clap(long, default_value = "0")]
+    min_lr: f64,
+    /// Learning rate factor of each step decay
+    #[clap(long, default_value = "0.1")]
+    gamma: f64,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum OptimizerChoice {
+    Adam,
+    Adamw,
+    /// SGD with momentum
+    Sgd,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Schedule {
+    Constant,
+    Cosine,
+    Step,
+}
+
+impl TrainOptions {
+    fn optimizer(&self) -> OptimizerKind {
+        match self.optimizer {
+            OptimizerChoice::Adam => OptimizerKind::Adam,
+            OptimizerChoice::Adamw => {
+                let adamw = AdamWConfig::new();
+                OptimizerKind::AdamW(match self.weight_decay {
+                    Some(weight_decay) => adamw.with_weight_decay(weight_decay as f32),
+                    None => adamw,
+                })
+            }
+            OptimizerChoice::Sgd => OptimizerKind::Sgd(
+                SgdConfig::new()
+                    .with_momentum(Some(MomentumConfig::new().with_momentum(self.momentum)))
+                    .with_weight_decay(self.weight_decay.map(WeightDecayConfig::new)),
+            ),
+        }
+    }
+
+    fn lr_schedule(&self) -> LrScheduleConfig {
+        let decay = match self.schedule {
+            Schedule::Constant => LrDecay::Constant,
+            Schedule::Cosine => LrDecay::Cosine {
+                steps: self.decay_steps,
+                min_lr: self.min_lr,
+            },
+            Schedule::Step => LrDecay::Step {
+                step_size: self.decay_steps,
+                gamma: self.gamma,
+            },
+        };
+        LrScheduleConfig::new()
+            .with_warmup_steps(self.warmup_steps)
+            .with_decay(decay)
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum ActivationChoice {
+    Relu,
+    Gelu,
+    Tanh,
+}
+
+impl From<ActivationChoice> for Activation {
+    fn from(activation: ActivationChoice) -> Self {
+        match activation {
+            ActivationChoice::Relu => Activation::Relu,
+            ActivationChoice::Gelu => Activation::Gelu,
+            ActivationChoice::Tanh => Activation::Tanh,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum NormChoice {
+    None,
+    /// Batch normalization
+    Batch,
+    /// Layer normalization
+    Layer,
+}
+
+impl From<NormChoice> for Norm {
+    fn from(norm: NormChoice) -> Self {
+        match norm {
+            NormChoice::None => Norm::None,
+            NormChoice::Batch => Norm::Batch,
+            NormChoice::Layer => Norm::Layer,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Balance {
+    /// Train on the dataset as it is
+    None,
+    /// Weight the loss by the inverse frequency of each class
+    Weighted,
+    /// Resample every class up to the size of the largest one
+    Oversample,
+    /// Sample every class down to the size of the smallest one
+    Undersample,
+}
+
+impl From<Balance> for ClassBalance {
+    fn from(balance: Balance) -> Self {
+        match balance {
+            Balance::None => ClassBalance::None,
+            Balance::Weighted => ClassBalance::Weighted,
+            Balance::Oversample => ClassBalance::Oversample,
+            Balance::Undersample => ClassBalance::Undersample,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Selection {
+    /// Lowest validation loss
+    Loss,
+    /// Highest validation accuracy
+    Accuracy,
+}
 
+impl From<Selection> for SelectionMetric {
+    fn from(selection: Selection) -> Self {
+        match selection {
+            Selection::Loss => SelectionMetric::Loss,
+            Selection::Accuracy => SelectionMetric::Accuracy,
+        }
+    }
+}
+
+#[derive(Debug, Parser)]
+struct TrainTreeOptions {
+    /// Directory where the trees, their config and the fitted artifacts are stored
+    #[clap(long, default_value = "./ml/tree")]
+    artifact_dir: String,
+    /// Labeled CSV the trees are trained on
+    #[clap(long, default_value = "./ml/data/train.csv")]
+    train_data: String,
+    /// Feature spec (features.json) of the features to split on, the default spec otherwise
+    #[clap(long)]
+    features: Option<String>,
+    /// 1 for a single CART tree, more for a random forest
+    #[clap(long, default_value = "1")]
+    trees: usize,
+    #[clap(long, default_value = "12")]
+    max_depth: usize,
+    #[clap(long, default_value = "2")]
+    min_samples_split: usize,
+    #[clap(long, default_value = "1")]
+    min_samples_leaf: usize,
+    /// Features considered at each split, all for a single tree and their square root for forests
+    #[clap(long)]
+    max_features: Option<usize>,
+    #[clap(long, default_value = "42")]
+    seed: u64,
+}
+
+#[derive(Debug, Parser)]
+struct EvalOptions {
+    /// Directory of the trained model
+    #[clap(long, default_value = "./ml/guide")]
+    artifact_dir: String,
+    /// Labeled CSV to score
+    #[clap(long, default_value = "./ml/data/test.csv")]
+    data: String,
+    #[clap(long, default_value = "1024")]
+    batch_size: usize,
+    /// Also write the report as JSON to this file
+    #[clap(long)]
+    json: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+struct PredictOptions {
+    /// Directory of the trained model
+    #[clap(long, default_value = "./ml/guide")]
+    artifact_dir: String,
+    /// CSV of flows to classify, the Label column is optional
+    #[clap(long)]
+    data: String,
+    #[clap(long, default_value = "1024")]
+    batch_size: usize,
+}
+
+fn prepare(opts: PrepareOptions) -> Result<(), Box<dyn Error>> {
+    let features = match opts.features {
+        Some(path) => FeatureSpec::load(path)?,
+        None => FeatureSpec::dataset(),
+    };
+    let config = PrepareConfig::new(features)
+        .with_train_ratio(opts.train_ratio)
+        .with_valid_ratio(opts.valid_ratio)
+        .with_seed(opts.seed);
+
+    let summary = config.prepare(&opts.inputs, &opts.output_dir)?;
+    println!(
+        "{} rows kept, {} invalid rows and {} repeated headers dropped",
+        summary.rows, summary.invalid_rows, summary.header_rows
+    );
+    for (label, rows) in summary.labels {
+        println!("{rows:>9} {label}");
+    }
+    Ok(())
+}
+
+fn train(opts: TrainOptions) -> Result<(), Box<dyn Error>> {
     let device = burn::backend::ndarray::NdArrayDevice::default();
-    let artifact_dir = "./ml/guide";
+    let dataset_train = IDSDataset::from_csv(&opts.train_data);
+    let dataset_valid = IDSDataset::from_csv(&opts.valid_data);
+
+    let features = match &opts.features {
+        Some(path) => FeatureSpec::load(path)?,
+        None => FeatureSpec::default(),
+    };
+    let labels = LabelVocab::fit(&dataset_train);
+    let model = ModelConfig::new(features.len(), opts.hidden_size, labels.len())
+        .with_dropout(opts.dropout)
+        .with_hidden_sizes(Some(opts.hidden_sizes.clone()))
+        .with_activation(Some(opts.activation.into()))
+        .with_norm(Some(opts.norm.into()))
+        .with_dropouts(Some(opts.dropouts.clone()));
+    let adam = AdamConfig::new().with_weight_decay(opts.weight_decay.map(WeightDecayConfig::new));
+    let config = TrainingConfig::new(model, adam)
+        .with_num_epochs(opts.epochs)
+        .with_batch_size(opts.batch_size)
+        .with_learning_rate(opts.learning_rate)
+        .with_seed(opts.seed)
+        .with_normalize(Some(!opts.no_normalize))
+        .with_log1p(Some(opts.log1p))
+        .with_class_balance(Some(opts.balance.into()))
+        .with_selection_metric(Some(opts.selection_metric.into()))
+        .with_patience(opts.patience)
+        .with_optimizer_kind(Some(opts.optimizer()))
+        .with_lr_schedule(Some(opts.lr_schedule()));
+
     ml::training::train::<MyAutodiffBackend>(
-        artifact_dir,
-        TrainingConfig::new(ModelConfig::new(5, 512, 2), AdamConfig::new()),
-        device.clone(),
+        &opts.artifact_dir,
+        config,
+        features,
+        labels,
+        dataset_train,
+        dataset_valid,
+        device,
     );
+    Ok(())
+}
+
+fn train_tree(opts: TrainTreeOptions) -> Result<(), Box<dyn Error>> {
+    let dataset_train = IDSDataset::from_csv(&opts.train_data);
+
+    let features = match opts.features {
+        Some(path) => FeatureSpec::load(path)?,
+        None => FeatureSpec::default(),
+    };
+    let labels = LabelVocab::fit(&dataset_train);
+    let config = ForestConfig::new()
+        .with_num_trees(opts.trees)
+        .with_max_depth(opts.max_depth)
+        .with_min_samples_split(opts.min_samples_split)
+        .with_min_samples_leaf(opts.min_samples_leaf)
+        .with_max_features(opts.max_features)
+        .with_seed(opts.seed);
+
+    ml::tree::train_trees(&opts.artifact_dir, config, features, labels, &dataset_train);
+    Ok(())
+}
+
+// trees when the artifact directory has some, the MLP otherwise
+fn classifier(artifact_dir: &str) -> Box<dyn Classifier> {
+    if std::path::Path::new(&format!("{artifact_dir}/tree.json")).exists() {
+        Box::new(TreeClassifier::new(artifact_dir))
+    } else {
+        let device = burn::backend::ndarray::NdArrayDevice::default();
+        Box::new(Detector::<MyBackend>::new(artifact_dir, device))
+    }
+}
+
+fn eval(opts: EvalOptions) -> Result<(), Box<dyn Error>> {
+    let classifier = classifier(&opts.artifact_dir);
+    let report = EvaluationReport::evaluate(classifier.as_ref(), &IDSDataset::from_csv(&opts.data), opts.batch_size);
+
+    report.print();
+    if let Some(path) = opts.json {
+        report.save(path)?;
+    }
+    Ok(())
+}
+
+fn predict(opts: PredictOptions) {
+    let classifier = classifier(&opts.artifact_dir);
+    let items: Vec<_> = IDSDataset::from_csv(&opts.data).iter().collect();
+    let batch_size = opts.batch_size.max(1);
+
+    println!("row,label,probability,attack,attack_score");
+    for (batch, chunk) in items.chunks(batch_size).enumerate() {
+        for (i, prediction) in classifier.predict_batch(chunk.to_vec()).into_iter().enumerate() {
+            println!(
+                "{},{},{:.4},{},{:.4}",
+                batch * batch_size + i,
+                prediction.label,
+                prediction.probability,
+                prediction.attack,
+                prediction.attack_score()
+            );
+        }
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let opts = Options::parse();
 
+    match opts.command {
+        Command::Prepare(opts) => prepare(opts)?,
+        Command::Train(opts) => train(opts)?,
+        Command::TrainTree(opts) => train_tree(opts)?,
+        Command::Eval(opts) => eval(opts)?,
+        Command::Predict(opts) => predict(opts),
+    }
 
   Ok(())
 }
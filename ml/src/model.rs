@@ -1,18 +1,123 @@
 use burn::{
     nn::{
-        Dropout, DropoutConfig, Linear, LinearConfig, Relu,
+        BatchNorm, BatchNormConfig, Dropout, DropoutConfig, Gelu, LayerNorm, LayerNormConfig,
+        Linear, LinearConfig, Relu,
     },
     prelude::*,
+    record::{CompactRecorder, Recorder},
 };
 
+/// Activation of the hidden layers
+#[derive(Config, Debug, Copy, PartialEq, Eq)]
+pub enum Activation {
+    Relu,
+    Gelu,
+    Tanh,
+}
+
+impl Activation {
+    fn init(&self) -> ActivationLayer {
+        match self {
+            Activation::Relu => ActivationLayer::Relu(Relu::new()),
+            Activation::Gelu => ActivationLayer::Gelu(Gelu::new()),
+            Activation::Tanh => ActivationLayer::Tanh(Tanh {}),
+        }
+    }
+}
+
+/// Tanh activation, which `burn::nn` does not provide
+#[derive(Module, Clone, Debug, Default)]
+pub struct Tanh {}
+
+impl Tanh {
+    fn forward<B: Backend>(&self, x: Tensor<B, 2>) -> Tensor<B, 2> {
+        x.tanh()
+    }
+}
+
+/// Activation module of a hidden layer, each variant wraps the layer of an `Activation`
+#[derive(Module, Clone, Debug)]
+pub enum ActivationLayer {
+    Relu(Relu),
+    Gelu(Gelu),
+    Tanh(Tanh),
+}
+
+impl ActivationLayer {
+    fn forward<B: Backend>(&self, x: Tensor<B, 2>) -> Tensor<B, 2> {
+        match self {
+            ActivationLayer::Relu(relu) => relu.forward(x),
+            ActivationLayer::Gelu(gelu) => gelu.forward(x),
+            ActivationLayer::Tanh(tanh) => tanh.forward(x),
+        }
+    }
+}
+
+/// Normalization of the hidden layers, between the linear layer and the activation
+#[derive(Config, Debug, Copy, PartialEq, Eq)]
+pub enum Norm {
+    None,
+    Batch,
+    Layer,
+}
+
+/// Linear layer followed by the optional normalization, the activation and dropout
+#[derive(Module, Debug)]
+pub struct HiddenLayer<B: Backend> {
+    linear: Linear<B>,
+    batch_norm: Option<BatchNorm<B, 0>>,
+    layer_norm: Option<LayerNorm<B>>,
+    activation: ActivationLayer,
+    dropout: Dropout,
+}
+
+impl<B: Backend> HiddenLayer<B> {
+    fn forward(&self, x: Tensor<B, 2>) -> Tensor<B, 2> {
+        let mut x = self.linear.forward(x);
+        if let Some(batch_norm) = &self.batch_norm {
+            x = batch_norm.forward(x);
+        }
+        if let Some(layer_norm) = &self.layer_norm {
+            x = layer_norm.forward(x);
+        }
+        let x = self.activation.forward(x);
+
+        self.dropout.forward(x)
+    }
+}
+
 #[derive(Module, Debug)]
 pub struct Model<B: Backend> {
+    hidden: Vec<HiddenLayer<B>>,
+    output: Linear<B>,
+}
+
+// single hidden layer model saved before the layers were configurable, as in ml/guide.lock
+#[derive(Module, Debug)]
+struct LegacyModel<B: Backend> {
     linear1: Linear<B>,
     linear2: Linear<B>,
     activation1: Relu,
     dropout: Dropout,
 }
 
+impl<B: Backend> From<LegacyModel<B>> for Model<B> {
+    // same inference, dropout only applies to training
+    fn from(legacy: LegacyModel<B>) -> Self {
+        let hidden = HiddenLayer {
+            linear: legacy.linear1,
+            batch_norm: None,
+            layer_norm: None,
+            activation: ActivationLayer::Relu(legacy.activation1),
+            dropout: legacy.dropout,
+        };
+
+        Model {
+            hidden: vec![hidden],
+            output: legacy.linear2,
+        }
+    }
+}
 
 #[derive(Config, Debug)]
 pub struct ModelConfig {
@@ -21,32 +126,94 @@ pub struct ModelConfig {
     num_classes: usize,
     #[config(default = "0.5")]
     dropout: f64,
+    // the layer settings below are optional so that the config.json of ml/guide.lock, saved
+    // before they existed, still loads
+    /// Widths of the hidden layers, a single layer of `hidden_size` when not set or empty
+    hidden_sizes: Option<Vec<usize>>,
+    /// `Activation::Relu` when not set
+    activation: Option<Activation>,
+    /// `Norm::None` when not set
+    norm: Option<Norm>,
+    /// Dropout of each hidden layer, `dropout` for the layers not listed
+    dropouts: Option<Vec<f64>>,
 }
 
 impl ModelConfig {
+    /// Width of the input layer, the length of the `FeatureSpec` the model is trained on
+    pub fn input_size(&self) -> usize {
+        self.input_size
+    }
+
+    /// Width of the output layer, the length of the `LabelVocab` the model is trained on
+    pub fn num_classes(&self) -> usize {
+        self.num_classes
+    }
+
+    fn layer_sizes(&self) -> Vec<usize> {
+        match &self.hidden_sizes {
+            Some(sizes) if !sizes.is_empty() => sizes.clone(),
+            _ => vec![self.hidden_size],
+        }
+    }
+
     /// Returns the initialized model.
     pub fn init<B: Backend>(&self, device: &B::Device) -> Model<B> {
+        let activation = self.activation.unwrap_or(Activation::Relu);
+        let norm = self.norm.unwrap_or(Norm::None);
+        let dropouts = self.dropouts.as_deref().unwrap_or_default();
+
+        let mut input_size = self.input_size;
+        let mut hidden = Vec::new();
+        for (i, size) in self.layer_sizes().into_iter().enumerate() {
+            let dropout = dropouts.get(i).copied().unwrap_or(self.dropout);
+            hidden.push(HiddenLayer {
+                linear: LinearConfig::new(input_size, size).init(device),
+                batch_norm: (norm == Norm::Batch).then(|| BatchNormConfig::new(size).init(device)),
+                layer_norm: (norm == Norm::Layer).then(|| LayerNormConfig::new(size).init(device)),
+                activation: activation.init(),
+                dropout: DropoutConfig::new(dropout).init(),
+            });
+            input_size = size;
+        }
+
         Model {
+            hidden,
+            output: LinearConfig::new(input_size, self.num_classes).init(device),
+        }
+    }
+
+    fn init_legacy<B: Backend>(&self, device: &B::Device) -> LegacyModel<B> {
+        LegacyModel {
             activation1: Relu::new(),
             linear1: LinearConfig::new(self.input_size, self.hidden_size).init(device),
             linear2: LinearConfig::new(self.hidden_size, self.num_classes).init(device),
             dropout: DropoutConfig::new(self.dropout).init(),
         }
     }
-}
 
+    /// Loads a model saved with `CompactRecorder`, including the single hidden layer models
+    /// saved before the layers were configurable
+    pub fn load<B: Backend>(&self, file: &str, device: &B::Device) -> Model<B> {
+        let recorder = CompactRecorder::new();
+        match recorder.load(file.into(), device) {
+            Ok(record) => self.init(device).load_record(record),
+            Err(_) => {
+                let record = recorder
+                    .load(file.into(), device)
+                    .expect("Trained model should exist");
+                self.init_legacy(device).load_record(record).into()
+            }
+        }
+    }
+}
 
 impl<B: Backend> Model<B> {
     /// # Shapes
     ///   - Input [batch_size, features]
     ///   - Output [batch_size, num_classes]
     pub fn forward(&self, data: Tensor<B, 2>) -> Tensor<B, 2> {
-        //let [batch_size, num_features] = data.dims();
-        
-        let x = self.linear1.forward(data);
-        let x = self.dropout.forward(x);
-        let x = self.activation1.forward(x);
+        let x = self.hidden.iter().fold(data, |x, layer| layer.forward(x));
 
-        self.linear2.forward(x) // [batch_size, num_classes]
+        self.output.forward(x) // [batch_size, num_classes]
     }
-}
\ No newline at end of file
+}
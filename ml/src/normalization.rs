@@ -0,0 +1,65 @@
+use crate::{data::IDSItem, features::FeatureSpec};
+use burn::{data::dataset::Dataset, prelude::*};
+
+/// Standard score of each feature of a `FeatureSpec`, optionally applied on log1p of the raw value.
+/// Fitted on the training set and saved as `normalizer.json` in the artifact directory.
+#[derive(Config, Debug)]
+pub struct Normalizer {
+    pub mean: Vec<f32>,
+    pub std: Vec<f32>,
+    #[config(default = false)]
+    pub log1p: bool,
+}
+
+// keeps the sign so negative values do not produce NaNs
+fn signed_log1p(value: f32) -> f32 {
+    value.signum() * value.abs().ln_1p()
+}
+
+impl Normalizer {
+    /// Computes the mean and standard deviation of each feature over the dataset
+    pub fn fit<D: Dataset<IDSItem>>(dataset: &D, features: &FeatureSpec, log1p: bool) -> Self {
+        let mut sum = vec![0.0f64; features.len()];
+        let mut sum_sq = vec![0.0f64; features.len()];
+        let mut count = 0.0f64;
+
+        for item in dataset.iter() {
+            for (i, value) in features.values(&item).into_iter().enumerate() {
+                let value = if log1p { signed_log1p(value) } else { value } as f64;
+                sum[i] += value;
+                sum_sq[i] += value * value;
+            }
+            count += 1.0;
+        }
+
+        let count = count.max(1.0);
+        let mean: Vec<f64> = sum.iter().map(|s| s / count).collect();
+        let std = sum_sq
+            .iter()
+            .zip(&mean)
+            .map(|(sq, m)| {
+                let std = (sq / count - m * m).max(0.0).sqrt() as f32;
+                // constant features are only centered
+                if std > f32::EPSILON {
+                    std
+                } else {
+                    1.0
+                }
+            })
+            .collect();
+
+        Normalizer::new(mean.into_iter().map(|m| m as f32).collect(), std).with_log1p(log1p)
+    }
+
+    /// Loads the normalizer of an artifact directory, models trained before it existed have none
+    pub fn load_optional(artifact_dir: &str) -> Option<Self> {
+        Normalizer::load(format!("{artifact_dir}/normalizer.json")).ok()
+    }
+
+    pub fn apply(&self, values: &mut [f32]) {
+        for ((value, mean), std) in values.iter_mut().zip(&self.mean).zip(&self.std) {
+            let raw = if self.log1p { signed_log1p(*value) } else { *value };
+            *value = (raw - mean) / std;
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use burn::{
+    lr_scheduler::LrScheduler,
+    optim::{AdamWConfig, SgdConfig},
+    prelude::*,
+    LearningRate,
+};
+use serde::{Deserialize, Serialize};
+
+/// Optimizer used for training. Adam keeps its settings in the `optimizer` field of
+/// `TrainingConfig`, so that configs saved before this choice existed keep loading.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum OptimizerKind {
+    Adam,
+    AdamW(AdamWConfig),
+    /// SGD, usually with momentum
+    Sgd(SgdConfig),
+}
+
+/// Decay of the learning rate after the warmup, in optimizer steps (batches)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum LrDecay {
+    Constant,
+    /// Cosine annealing down to `min_lr` over `steps`, then constant
+    Cosine {
+        steps: usize,
+        min_lr: f64,
+    },
+    /// Multiplied by `gamma` every `step_size` steps
+    Step {
+        step_size: usize,
+        gamma: f64,
+    },
+}
+
+/// Learning rate schedule around the `learning_rate` of `TrainingConfig`
+#[derive(Config, Debug)]
+pub struct LrScheduleConfig {
+    /// Steps of linear increase from 0 to the learning rate
+    #[config(default = 0)]
+    pub warmup_steps: usize,
+    #[config(default = "LrDecay::Constant")]
+    pub decay: LrDecay,
+}
+
+impl LrScheduleConfig {
+    pub fn init(&self, learning_rate: LearningRate) -> LrSchedule {
+        LrSchedule {
+            config: self.clone(),
+            learning_rate,
+            step: 0,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct LrSchedule {
+    config: LrScheduleConfig,
+    learning_rate: LearningRate,
+    step: usize,
+}
+
+impl LrSchedule {
+    /// Learning rate of a step, starting at 1
+    pub fn rate(&self, step: usize) -> LearningRate {
+        let warmup = self.config.warmup_steps;
+        if warmup > 0 && step <= warmup {
+            return self.learning_rate * step as f64 / warmup as f64;
+        }
+
+        let step = step - warmup;
+        match self.config.decay {
+            LrDecay::Constant => self.learning_rate,
+            LrDecay::Cosine { steps, min_lr } => {
+                let progress = (step as f64 / steps.max(1) as f64).min(1.0);
+                let cosine = (1.0 + (std::f64::consts::PI * progress).cos()) / 2.0;
+                min_lr + (self.learning_rate - min_lr) * cosine
+            }
+            LrDecay::Step { step_size, gamma } => {
+                self.learning_rate * gamma.powi((step / step_size.max(1)) as i32)
+            }
+        }
+    }
+}
+
+impl<B: Backend> LrScheduler<B> for LrSchedule {
+    // the step, so that resumed trainings continue the schedule
+    type Record = usize;
+
+    fn step(&mut self) -> LearningRate {
+        self.step += 1;
+        self.rate(self.step)
+    }
+
+    fn to_record(&self) -> Self::Record {
+        self.step
+    }
+
+    fn load_record(mut self, record: Self::Record) -> Self {
+        self.step = record;
+        self
+    }
+}
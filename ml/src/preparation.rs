@@ -0,0 +1,180 @@
+use crate::features::FeatureSpec;
+use burn::prelude::*;
+use csv::{ReaderBuilder, StringRecord, Trim, WriterBuilder};
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use std::{collections::BTreeMap, error::Error};
+
+const LABEL: &str = "Label";
+
+// names of the original CIC-IDS2017 per-day CSVs for the columns renamed in the training CSVs
+const ALIASES: [(&str, &str); 5] = [
+    ("Destination Port", "Dst Port"),
+    ("Total Fwd Packets", "Total Fwd Packet"),
+    ("Total Backward Packets", "Total Bwd packets"),
+    ("Total Length of Fwd Packets", "Total Length of Fwd Packet"),
+    ("Total Length of Bwd Packets", "Total Length of Bwd Packet"),
+];
+
+/// Columns and proportions of a train/validation/test split, saved as `prepare.json` next to it
+#[derive(Config, Debug)]
+pub struct PrepareConfig {
+    /// Columns written to the split, besides the label
+    pub features: FeatureSpec,
+    #[config(default = 0.7)]
+    pub train_ratio: f64,
+    #[config(default = 0.15)]
+    pub valid_ratio: f64,
+    #[config(default = 42)]
+    pub seed: u64,
+}
+
+/// Rows kept and dropped while reading the raw CSVs
+#[derive(Debug, Default)]
+pub struct PrepareSummary {
+    pub rows: usize,
+    pub header_rows: usize,
+    pub invalid_rows: usize,
+    /// Rows of each label
+    pub labels: BTreeMap<String, usize>,
+}
+
+fn canonical_column(name: &str) -> &str {
+    ALIASES
+        .iter()
+        .find(|(original, _)| *original == name)
+        .map(|(_, column)| *column)
+        .unwrap_or(name)
+}
+
+// some labels of the original files contain a mis-encoded dash, e.g. "Web Attack � Brute Force"
+fn clean_label(label: &str) -> String {
+    let label: String = label
+        .trim()
+        .chars()
+        .map(|c| if c.is_ascii() { c } else { '-' })
+        .collect();
+    label.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// NaN, Infinity and empty values are found in the flow rate columns of the original files
+fn is_finite(value: &str) -> bool {
+    value
+        .trim()
+        .parse::<f64>()
+        .map(|value| value.is_finite())
+        .unwrap_or(false)
+}
+
+// position of the selected columns and of the label, first occurrence of duplicated names
+fn column_positions(
+    headers: &StringRecord,
+    columns: &[&str],
+    path: &str,
+) -> Result<Vec<usize>, Box<dyn Error>> {
+    columns
+        .iter()
+        .map(|column| {
+            headers
+                .iter()
+                .position(|header| canonical_column(header) == *column)
+                .ok_or_else(|| format!("Column {column:?} is missing from {path}").into())
+        })
+        .collect()
+}
+
+impl PrepareConfig {
+    /// Cleans the raw CSVs and writes a stratified `train.csv`, `valid.csv` and `test.csv` to `output_dir`
+    pub fn prepare(
+        &self,
+        inputs: &[String],
+        output_dir: &str,
+    ) -> Result<PrepareSummary, Box<dyn Error>> {
+        assert!(
+            self.train_ratio + self.valid_ratio <= 1.0,
+            "Train and validation ratios should leave room for the test set"
+        );
+
+        let mut columns: Vec<&str> = self
+            .features
+            .features
+            .iter()
+            .map(|feature| feature.column())
+            .collect();
+        columns.push(LABEL);
+
+        let mut summary = PrepareSummary::default();
+        let mut by_label: BTreeMap<String, Vec<StringRecord>> = BTreeMap::new();
+
+        for path in inputs {
+            let mut reader = ReaderBuilder::new()
+                .has_headers(true)
+                .trim(Trim::Headers)
+                .flexible(true)
+                .from_path(path)?;
+            let positions = column_positions(&reader.headers()?.clone(), &columns, path)?;
+            let label_position = positions[positions.len() - 1];
+
+            for record in reader.byte_records() {
+                // the original files are not valid UTF-8
+                let record = record?;
+                let field = |position: usize| {
+                    String::from_utf8_lossy(record.get(position).unwrap_or_default()).into_owned()
+                };
+
+                // concatenated files repeat their header
+                if field(label_position).trim() == LABEL {
+                    summary.header_rows += 1;
+                    continue;
+                }
+
+                let label = clean_label(&field(label_position));
+                let values: Vec<String> = positions[..positions.len() - 1]
+                    .iter()
+                    .map(|&position| field(position).trim().to_string())
+                    .collect();
+                if label.is_empty() || !values.iter().all(|value| is_finite(value)) {
+                    summary.invalid_rows += 1;
+                    continue;
+                }
+
+                let mut row = StringRecord::from(values);
+                row.push_field(&label);
+                by_label.entry(label).or_default().push(row);
+                summary.rows += 1;
+            }
+        }
+
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut splits: [Vec<StringRecord>; 3] = Default::default();
+        for (label, mut rows) in by_label {
+            summary.labels.insert(label, rows.len());
+
+            // every label keeps the same proportions in the three sets
+            rows.shuffle(&mut rng);
+            let train = (rows.len() as f64 * self.train_ratio).round() as usize;
+            let valid =
+                ((rows.len() as f64 * self.valid_ratio).round() as usize).min(rows.len() - train);
+            let test = rows.split_off(train + valid);
+            let valid = rows.split_off(train);
+
+            splits[0].extend(rows);
+            splits[1].extend(valid);
+            splits[2].extend(test);
+        }
+
+        std::fs::create_dir_all(output_dir)?;
+        for (name, mut rows) in ["train", "valid", "test"].into_iter().zip(splits) {
+            rows.shuffle(&mut rng);
+
+            let mut writer = WriterBuilder::new().from_path(format!("{output_dir}/{name}.csv"))?;
+            writer.write_record(&columns)?;
+            for row in rows {
+                writer.write_record(&row)?;
+            }
+            writer.flush()?;
+        }
+        self.save(format!("{output_dir}/prepare.json"))?;
+
+        Ok(summary)
+    }
+}
@@ -1,24 +1,53 @@
 use crate::{
+    balance::{class_weights, ClassBalance, ResampledDataset},
     data::{IDSBatch, IDSBatcher, IDSDataset},
+    features::FeatureSpec,
+    labels::LabelVocab,
     model::{Model, ModelConfig},
+    normalization::Normalizer,
+    optimization::{LrScheduleConfig, OptimizerKind},
 };
+use std::sync::Arc;
 use burn::{
-    data::dataloader::DataLoaderBuilder, optim::AdamConfig, prelude::*, record::CompactRecorder, tensor::backend::AutodiffBackend, train::{
-        metric::{AccuracyMetric, LossMetric},
-        ClassificationOutput, LearnerBuilder, TrainOutput, TrainStep, ValidStep,
-    }
+    data::dataloader::{DataLoader, DataLoaderBuilder}, optim::{AdamConfig, Optimizer}, prelude::*, record::{CompactRecorder, Recorder}, tensor::backend::AutodiffBackend, train::{
+        checkpoint::MetricCheckpointingStrategy,
+        metric::{
+            store::{Aggregate, Direction, Split},
+            AccuracyMetric, LossMetric,
+        },
+        ClassificationOutput, LearnerBuilder, MetricEarlyStoppingStrategy, StoppingCondition,
+        TrainOutput, TrainStep, ValidStep,
+    }, tensor::activation::log_softmax
 };
 use nn::loss::CrossEntropyLoss;
 
+// cross entropy of each item scaled by its weight, normalized by the sum of the weights
+fn weighted_cross_entropy<B: Backend>(
+    output: Tensor<B, 2>,
+    targets: Tensor<B, 1, Int>,
+    weights: Tensor<B, 1>,
+) -> Tensor<B, 1> {
+    let [batch_size, _] = output.dims();
+    let log_probs = log_softmax(output, 1)
+        .gather(1, targets.reshape([batch_size, 1]))
+        .reshape([batch_size]);
+
+    (log_probs * weights.clone()).sum().neg() / weights.sum()
+}
+
 
 impl<B: Backend> Model<B> {
     pub fn forward_classification(
         &self,
         data: Tensor<B, 2>,
         targets: Tensor<B, 1, Int>,
+        weights: Option<Tensor<B, 1>>,
     ) -> ClassificationOutput<B> {
         let output = self.forward(data);
-        let loss = CrossEntropyLoss::new(None, &output.device()).forward(output.clone(), targets.clone());
+        let loss = match weights {
+            Some(weights) => weighted_cross_entropy(output.clone(), targets.clone(), weights),
+            None => CrossEntropyLoss::new(None, &output.device()).forward(output.clone(), targets.clone()),
+        };
 
         ClassificationOutput::new(loss, output, targets)
     }
@@ -27,7 +56,7 @@ impl<B: Backend> Model<B> {
 
 impl<B: AutodiffBackend> TrainStep<IDSBatch<B>, ClassificationOutput<B>> for Model<B> {
     fn step(&self, batch: IDSBatch<B>) -> TrainOutput<ClassificationOutput<B>> {
-        let item = self.forward_classification(batch.flows, batch.targets);
+        let item = self.forward_classification(batch.flows, batch.targets, batch.weights);
 
         TrainOutput::new(self, item.loss.backward(), item)
     }
@@ -35,7 +64,58 @@ impl<B: AutodiffBackend> TrainStep<IDSBatch<B>, ClassificationOutput<B>> for Mod
 
 impl<B: Backend> ValidStep<IDSBatch<B>, ClassificationOutput<B>> for Model<B> {
     fn step(&self, batch: IDSBatch<B>) -> ClassificationOutput<B> {
-        self.forward_classification(batch.flows, batch.targets)
+        self.forward_classification(batch.flows, batch.targets, batch.weights)
+    }
+}
+
+/// Validation metric used to keep the best checkpoint and to stop training early
+#[derive(Config, Debug, Copy, PartialEq, Eq)]
+pub enum SelectionMetric {
+    Loss,
+    Accuracy,
+}
+
+impl SelectionMetric {
+    fn direction(&self) -> Direction {
+        match self {
+            SelectionMetric::Loss => Direction::Lowest,
+            SelectionMetric::Accuracy => Direction::Highest,
+        }
+    }
+
+    fn checkpointing<B: Backend>(&self) -> MetricCheckpointingStrategy {
+        let direction = self.direction();
+        match self {
+            SelectionMetric::Loss => MetricCheckpointingStrategy::new::<LossMetric<B>>(
+                Aggregate::Mean,
+                direction,
+                Split::Valid,
+            ),
+            SelectionMetric::Accuracy => MetricCheckpointingStrategy::new::<AccuracyMetric<B>>(
+                Aggregate::Mean,
+                direction,
+                Split::Valid,
+            ),
+        }
+    }
+
+    fn early_stopping<B: Backend>(&self, patience: usize) -> MetricEarlyStoppingStrategy {
+        let direction = self.direction();
+        let condition = StoppingCondition::NoImprovementSince { n_epochs: patience };
+        match self {
+            SelectionMetric::Loss => MetricEarlyStoppingStrategy::new::<LossMetric<B>>(
+                Aggregate::Mean,
+                direction,
+                Split::Valid,
+                condition,
+            ),
+            SelectionMetric::Accuracy => MetricEarlyStoppingStrategy::new::<AccuracyMetric<B>>(
+                Aggregate::Mean,
+                direction,
+                Split::Valid,
+                condition,
+            ),
+        }
     }
 }
 
@@ -53,55 +133,188 @@ pub struct TrainingConfig {
     pub seed: u64,
     #[config(default = 1.0e-4)]
     pub learning_rate: f64,
+    /// Fit a `Normalizer` on the training set and apply it to the features, true when not set
+    pub normalize: Option<bool>,
+    /// Normalize log1p of the features, for heavy tailed counters
+    pub log1p: Option<bool>,
+    /// How rare classes are compensated for, `ClassBalance::None` when not set
+    pub class_balance: Option<ClassBalance>,
+    /// Validation metric of the checkpoint saved as the model, `SelectionMetric::Loss` when not set
+    pub selection_metric: Option<SelectionMetric>,
+    /// Stop after this many epochs without improving the selection metric
+    pub patience: Option<usize>,
+    /// `OptimizerKind::Adam` when not set
+    pub optimizer_kind: Option<OptimizerKind>,
+    /// Constant learning rate when not set
+    pub lr_schedule: Option<LrScheduleConfig>,
 }
 
-fn create_artifact_dir(artifact_dir: &str) {
+// the settings added after ml/guide.lock was trained are optional, so that its config.json
+// still loads, and resolved to their default here
+impl TrainingConfig {
+    pub fn normalize(&self) -> bool {
+        self.normalize.unwrap_or(true)
+    }
+
+    pub fn log1p(&self) -> bool {
+        self.log1p.unwrap_or(false)
+    }
+
+    pub fn class_balance(&self) -> ClassBalance {
+        self.class_balance.unwrap_or(ClassBalance::None)
+    }
+
+    pub fn selection_metric(&self) -> SelectionMetric {
+        self.selection_metric.unwrap_or(SelectionMetric::Loss)
+    }
+
+    pub fn optimizer_kind(&self) -> OptimizerKind {
+        self.optimizer_kind.clone().unwrap_or(OptimizerKind::Adam)
+    }
+
+    pub fn lr_schedule(&self) -> LrScheduleConfig {
+        self.lr_schedule.clone().unwrap_or_else(LrScheduleConfig::new)
+    }
+}
+
+// only the best checkpoint is kept by the checkpointing strategy
+fn best_checkpoint(artifact_dir: &str) -> Option<usize> {
+    std::fs::read_dir(format!("{artifact_dir}/checkpoint"))
+        .ok()?
+        .filter_map(|entry| {
+            let name = entry.ok()?.file_name().into_string().ok()?;
+            name.strip_prefix("model-")?.strip_suffix(".mpk")?.parse().ok()
+        })
+        .max()
+}
+
+// the learner type depends on the optimizer
+fn fit<B: AutodiffBackend, O: Optimizer<Model<B>, B> + 'static>(
+    artifact_dir: &str,
+    config: &TrainingConfig,
+    optimizer: O,
+    dataloader_train: Arc<dyn DataLoader<IDSBatch<B>>>,
+    dataloader_valid: Arc<dyn DataLoader<IDSBatch<B::InnerBackend>>>,
+    device: &B::Device,
+) -> Model<B> {
+    let mut builder = LearnerBuilder::new(artifact_dir)
+        .metric_train_numeric(AccuracyMetric::new())
+        .metric_valid_numeric(AccuracyMetric::new())
+        .metric_train_numeric(LossMetric::new())
+        .metric_valid_numeric(LossMetric::new())
+        .with_file_checkpointer(CompactRecorder::new())
+        .devices(vec![device.clone()])
+        .num_epochs(config.num_epochs)
+        .summary();
+    builder.with_checkpointing_strategy(config.selection_metric().checkpointing::<B>());
+    if let Some(patience) = config.patience {
+        builder = builder.early_stopping(config.selection_metric().early_stopping::<B>(patience));
+    }
+
+    let learner = builder.build(
+        config.model.init::<B>(device),
+        optimizer,
+        config.lr_schedule().init(config.learning_rate),
+    );
+
+    learner.fit(dataloader_train, dataloader_valid)
+}
+
+pub(crate) fn create_artifact_dir(artifact_dir: &str) {
     // Remove existing artifacts before to get an accurate learner summary
     std::fs::remove_dir_all(artifact_dir).ok();
     std::fs::create_dir_all(artifact_dir).ok();
 }
 
-pub fn train<B: AutodiffBackend>(artifact_dir: &str, config: TrainingConfig, device: B::Device) {
+pub fn train<B: AutodiffBackend>(
+    artifact_dir: &str,
+    config: TrainingConfig,
+    features: FeatureSpec,
+    labels: LabelVocab,
+    dataset_train: IDSDataset,
+    dataset_valid: IDSDataset,
+    device: B::Device,
+) {
+    assert_eq!(
+        config.model.input_size(),
+        features.len(),
+        "Model input size should match the feature spec"
+    );
+    assert_eq!(
+        config.model.num_classes(),
+        labels.len(),
+        "Model classes should match the label vocabulary"
+    );
+
     create_artifact_dir(artifact_dir);
     config
         .save(format!("{artifact_dir}/config.json"))
         .expect("Config should be saved successfully");
+    features
+        .save(format!("{artifact_dir}/features.json"))
+        .expect("Feature spec should be saved successfully");
+    labels
+        .save(format!("{artifact_dir}/labels.json"))
+        .expect("Label vocabulary should be saved successfully");
 
     B::seed(config.seed);
 
-    let batcher_train = IDSBatcher::<B>::new(device.clone());
-    let batcher_valid = IDSBatcher::<B::InnerBackend>::new(device.clone());
+    let normalizer = config
+        .normalize()
+        .then(|| Normalizer::fit(&dataset_train, &features, config.log1p()));
+    if let Some(normalizer) = &normalizer {
+        normalizer
+            .save(format!("{artifact_dir}/normalizer.json"))
+            .expect("Normalizer should be saved successfully");
+    }
+
+    let weights = (config.class_balance() == ClassBalance::Weighted)
+        .then(|| class_weights(&dataset_train, &labels));
+    let dataset_train = ResampledDataset::new(dataset_train, config.class_balance(), config.seed);
+
+    let batcher_train = IDSBatcher::<B>::new(device.clone(), features.clone(), labels.clone())
+        .with_normalizer(normalizer.clone())
+        .with_class_weights(weights);
+    let batcher_valid = IDSBatcher::<B::InnerBackend>::new(device.clone(), features, labels)
+        .with_normalizer(normalizer);
 
     let dataloader_train = DataLoaderBuilder::new(batcher_train)
         .batch_size(config.batch_size)
         .shuffle(config.seed)
         .num_workers(config.num_workers)
-        .build(IDSDataset::train());
+        .build(dataset_train);
 
     let dataloader_test = DataLoaderBuilder::new(batcher_valid)
         .batch_size(config.batch_size)
         .shuffle(config.seed)
         .num_workers(config.num_workers)
-        .build(IDSDataset::test());
+        .build(dataset_valid);
 
-    let learner = LearnerBuilder::new(artifact_dir)
-        .metric_train_numeric(AccuracyMetric::new())
-        .metric_valid_numeric(AccuracyMetric::new())
-        .metric_train_numeric(LossMetric::new())
-        .metric_valid_numeric(LossMetric::new())
-        .with_file_checkpointer(CompactRecorder::new())
-        .devices(vec![device.clone()])
-        .num_epochs(config.num_epochs)
-        .summary()
-        .build(
-            config.model.init::<B>(&device),
-            config.optimizer.init(),
-            config.learning_rate,
-        );
+    let mut model_trained = match config.optimizer_kind() {
+        OptimizerKind::Adam => {
+            let optimizer = config.optimizer.init::<B, Model<B>>();
+            fit(artifact_dir, &config, optimizer, dataloader_train, dataloader_test, &device)
+        }
+        OptimizerKind::AdamW(adamw) => {
+            let optimizer = adamw.init::<B, Model<B>>();
+            fit(artifact_dir, &config, optimizer, dataloader_train, dataloader_test, &device)
+        }
+        OptimizerKind::Sgd(sgd) => {
+            let optimizer = sgd.init::<B, Model<B>>();
+            fit(artifact_dir, &config, optimizer, dataloader_train, dataloader_test, &device)
+        }
+    };
 
-    let model_trained = learner.fit(dataloader_train, dataloader_test);
+    // the last epoch is not necessarily the best one
+    if let Some(epoch) = best_checkpoint(artifact_dir) {
+        println!("Saving the model of epoch {epoch}");
+        let record = CompactRecorder::new()
+            .load(format!("{artifact_dir}/checkpoint/model-{epoch}").into(), &device)
+            .expect("Best checkpoint should be loaded successfully");
+        model_trained = model_trained.load_record(record);
+    }
 
     model_trained
         .save_file(format!("{artifact_dir}/model"), &CompactRecorder::new())
         .expect("Trained model should be saved successfully");
-}
\ No newline at end of file
+}
@@ -0,0 +1,354 @@
+use crate::{
+    data::IDSItem,
+    features::FeatureSpec,
+    inference::{Classifier, Prediction},
+    labels::LabelVocab,
+    training::create_artifact_dir,
+};
+use burn::{data::dataset::Dataset, prelude::*};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+/// `feature` of the leaves
+pub const LEAF: u32 = u32::MAX;
+
+/// Node of a tree stored as a flat array, as it would be in a BPF map
+#[derive(Config, Debug)]
+pub struct TreeNode {
+    /// Position in the `FeatureSpec` of the feature tested, `LEAF` for leaves
+    pub feature: u32,
+    /// Flows with `value <= threshold` go to `left`, the others to `right`. Integer, so that the
+    /// comparison can be done on the kernel counters
+    pub threshold: i64,
+    pub left: u32,
+    pub right: u32,
+    /// Training flows of each class that reached the leaf, empty for the other nodes
+    pub counts: Vec<u32>,
+}
+
+impl TreeNode {
+    fn leaf(counts: Vec<u32>) -> Self {
+        TreeNode::new(LEAF, 0, 0, 0, counts)
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.feature == LEAF
+    }
+}
+
+/// Decision tree, the root is the first node
+#[derive(Config, Debug)]
+pub struct Tree {
+    pub nodes: Vec<TreeNode>,
+}
+
+impl Tree {
+    /// Leaf reached by a feature vector
+    pub fn leaf(&self, values: &[f32]) -> &TreeNode {
+        let mut node = &self.nodes[0];
+        while !node.is_leaf() {
+            let next = if values[node.feature as usize] as f64 <= node.threshold as f64 {
+                node.left
+            } else {
+                node.right
+            };
+            node = &self.nodes[next as usize];
+        }
+        node
+    }
+}
+
+/// Trees saved as `tree.json` in the artifact directory
+#[derive(Config, Debug)]
+pub struct Forest {
+    pub trees: Vec<Tree>,
+}
+
+impl Forest {
+    /// Mean over the trees of the class frequencies of the leaf reached by a feature vector
+    pub fn probabilities(&self, values: &[f32], num_classes: usize) -> Vec<f32> {
+        let mut probabilities = vec![0.0; num_classes];
+        for tree in &self.trees {
+            let counts = &tree.leaf(values).counts;
+            let total: u32 = counts.iter().sum();
+            for (probability, count) in probabilities.iter_mut().zip(counts) {
+                *probability += *count as f32 / total.max(1) as f32 / self.trees.len() as f32;
+            }
+        }
+        probabilities
+    }
+}
+
+/// Settings of the trees, saved as `tree_config.json` in the artifact directory
+#[derive(Config, Debug)]
+pub struct ForestConfig {
+    /// 1 trains a single CART tree on the whole dataset, more a random forest
+    #[config(default = 1)]
+    pub num_trees: usize,
+    #[config(default = 12)]
+    pub max_depth: usize,
+    #[config(default = 2)]
+    pub min_samples_split: usize,
+    #[config(default = 1)]
+    pub min_samples_leaf: usize,
+    /// Features considered at each split, all of them for a single tree and the square root of
+    /// their number for forests when not set
+    pub max_features: Option<usize>,
+    #[config(default = 42)]
+    pub seed: u64,
+}
+
+// Gini impurity of n samples
+fn gini(counts: impl Iterator<Item = u32>, n: usize) -> f64 {
+    if n == 0 {
+        return 0.0;
+    }
+    1.0 - counts
+        .map(|count| (count as f64 / n as f64).powi(2))
+        .sum::<f64>()
+}
+
+struct TreeBuilder<'a> {
+    config: &'a ForestConfig,
+    values: &'a [Vec<f32>],
+    classes: &'a [usize],
+    num_classes: usize,
+    max_features: usize,
+    rng: StdRng,
+    nodes: Vec<TreeNode>,
+}
+
+impl TreeBuilder<'_> {
+    fn counts(&self, indices: &[usize]) -> Vec<u32> {
+        let mut counts = vec![0; self.num_classes];
+        for &i in indices {
+            counts[self.classes[i]] += 1;
+        }
+        counts
+    }
+
+    // grows the subtree of the samples, returns the position of its root
+    fn grow(&mut self, indices: Vec<usize>, depth: usize) -> u32 {
+        let counts = self.counts(&indices);
+        let id = self.nodes.len() as u32;
+
+        let pure = counts.iter().filter(|&&count| count > 0).count() <= 1;
+        let split = if depth >= self.config.max_depth
+            || indices.len() < self.config.min_samples_split
+            || pure
+        {
+            None
+        } else {
+            self.best_split(&indices, &counts)
+        };
+
+        let Some((feature, threshold)) = split else {
+            self.nodes.push(TreeNode::leaf(counts));
+            return id;
+        };
+
+        self.nodes
+            .push(TreeNode::new(feature as u32, threshold, 0, 0, Vec::new()));
+        let (left, right): (Vec<usize>, Vec<usize>) = indices
+            .into_iter()
+            .partition(|&i| self.values[i][feature] as f64 <= threshold as f64);
+        let left = self.grow(left, depth + 1);
+        let right = self.grow(right, depth + 1);
+
+        let node = &mut self.nodes[id as usize];
+        node.left = left;
+        node.right = right;
+        id
+    }
+
+    // feature and integer threshold with the lowest Gini impurity of the children
+    fn best_split(&mut self, indices: &[usize], counts: &[u32]) -> Option<(usize, i64)> {
+        let n = indices.len();
+        let mut best_impurity = gini(counts.iter().copied(), n);
+        let mut best = None;
+
+        let mut features: Vec<usize> = (0..self.values[indices[0]].len()).collect();
+        features.shuffle(&mut self.rng);
+        features.truncate(self.max_features);
+
+        for feature in features {
+            let mut sorted: Vec<(f32, usize)> = indices
+                .iter()
+                .map(|&i| (self.values[i][feature], self.classes[i]))
+                .collect();
+            sorted.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+            let mut left = vec![0u32; self.num_classes];
+            for i in 0..n - 1 {
+                left[sorted[i].1] += 1;
+
+                let (n_left, n_right) = (i + 1, n - i - 1);
+                if n_left < self.config.min_samples_leaf || n_right < self.config.min_samples_leaf {
+                    continue;
+                }
+
+                // only integer thresholds between two consecutive values
+                let threshold = sorted[i].0.ceil();
+                if threshold >= sorted[i + 1].0 {
+                    continue;
+                }
+
+                let right = counts.iter().zip(&left).map(|(count, left)| count - left);
+                let impurity = (n_left as f64 * gini(left.iter().copied(), n_left)
+                    + n_right as f64 * gini(right, n_right))
+                    / n as f64;
+                if impurity < best_impurity {
+                    best_impurity = impurity;
+                    best = Some((feature, threshold as i64));
+                }
+            }
+        }
+        best
+    }
+}
+
+impl ForestConfig {
+    // trains the `tree`-th tree, seeded from its position so that it does not depend on the thread
+    fn fit_tree(
+        &self,
+        tree: usize,
+        values: &[Vec<f32>],
+        classes: &[usize],
+        num_classes: usize,
+        max_features: usize,
+    ) -> Tree {
+        let mut rng = StdRng::seed_from_u64(self.seed + tree as u64);
+        // forests train every tree on a bootstrap sample
+        let indices: Vec<usize> = if self.num_trees > 1 {
+            (0..values.len())
+                .map(|_| rng.gen_range(0..values.len()))
+                .collect()
+        } else {
+            (0..values.len()).collect()
+        };
+
+        let mut builder = TreeBuilder {
+            config: self,
+            values,
+            classes,
+            num_classes,
+            max_features,
+            rng,
+            nodes: Vec::new(),
+        };
+        builder.grow(indices, 0);
+        Tree::new(builder.nodes)
+    }
+
+    /// Trains the trees on feature vectors and their class, in parallel on at most one thread
+    /// per core
+    pub fn fit(&self, values: &[Vec<f32>], classes: &[usize], num_classes: usize) -> Forest {
+        let num_features = values.first().map(Vec::len).unwrap_or_default();
+        let max_features = self
+            .max_features
+            .unwrap_or(if self.num_trees > 1 {
+                (num_features as f64).sqrt().round() as usize
+            } else {
+                num_features
+            })
+            .clamp(1, num_features.max(1));
+
+        // consecutive trees per thread, so that the forest keeps their order
+        let threads = std::thread::available_parallelism().map_or(1, usize::from);
+        let per_thread = self.num_trees.div_ceil(threads).max(1);
+
+        let trees = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..self.num_trees)
+                .step_by(per_thread)
+                .map(|first| {
+                    let last = (first + per_thread).min(self.num_trees);
+                    scope.spawn(move || {
+                        (first..last)
+                            .map(|tree| {
+                                self.fit_tree(tree, values, classes, num_classes, max_features)
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().expect("Trees should be trained successfully"))
+                .collect()
+        });
+
+        Forest::new(trees)
+    }
+}
+
+/// Trees loaded from an artifact directory, scoring flows like the MLP `Detector`
+pub struct TreeClassifier {
+    forest: Forest,
+    features: FeatureSpec,
+    labels: LabelVocab,
+}
+
+impl TreeClassifier {
+    pub fn new(artifact_dir: &str) -> Self {
+        Self {
+            forest: Forest::load(format!("{artifact_dir}/tree.json"))
+                .expect("Trained trees should exist"),
+            features: FeatureSpec::load_or_default(artifact_dir),
+            labels: LabelVocab::load_or_default(artifact_dir),
+        }
+    }
+}
+
+impl Classifier for TreeClassifier {
+    fn labels(&self) -> &LabelVocab {
+        &self.labels
+    }
+
+    fn predict_batch(&self, items: Vec<IDSItem>) -> Vec<Prediction> {
+        items
+            .iter()
+            .map(|item| {
+                let values = self.features.values(item);
+                let probabilities = self.forest.probabilities(&values, self.labels.len());
+                Prediction::new(&self.labels, probabilities)
+            })
+            .collect()
+    }
+}
+
+/// Trains trees on the raw features of the dataset, not normalized so that the thresholds
+/// stay in the units of the flow counters
+pub fn train_trees<D: Dataset<IDSItem>>(
+    artifact_dir: &str,
+    config: ForestConfig,
+    features: FeatureSpec,
+    labels: LabelVocab,
+    dataset: &D,
+) {
+    create_artifact_dir(artifact_dir);
+    config
+        .save(format!("{artifact_dir}/tree_config.json"))
+        .expect("Config should be saved successfully");
+    features
+        .save(format!("{artifact_dir}/features.json"))
+        .expect("Feature spec should be saved successfully");
+    labels
+        .save(format!("{artifact_dir}/labels.json"))
+        .expect("Label vocabulary should be saved successfully");
+
+    let mut values = Vec::with_capacity(dataset.len());
+    let mut classes = Vec::with_capacity(dataset.len());
+    for item in dataset.iter() {
+        let class = labels
+            .index(&item.label)
+            .unwrap_or_else(|| panic!("Label {:?} should be in the vocabulary", item.label));
+        values.push(features.values(&item));
+        classes.push(class);
+    }
+    assert!(!values.is_empty(), "Training set should not be empty");
+
+    config
+        .fit(&values, &classes, labels.len())
+        .save(format!("{artifact_dir}/tree.json"))
+        .expect("Trees should be saved successfully");
+}
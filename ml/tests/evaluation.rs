@@ -0,0 +1,108 @@
+// Checks the benign vs attack AUCs on small rankings with known areas, and that labels outside
+// the vocabulary are reported instead of aborting the evaluation.
+
+use burn::data::dataset::InMemDataset;
+use csv::ReaderBuilder;
+use ml::{
+    data::IDSItem,
+    evaluation::{pr_auc, roc_auc, EvaluationReport},
+    inference::{Classifier, Prediction},
+    labels::LabelVocab,
+};
+
+fn assert_close(actual: f64, expected: f64) {
+    assert!(
+        (actual - expected).abs() < 1e-9,
+        "got {actual}, expected {expected}"
+    );
+}
+
+#[test]
+fn tied_scores_are_random() {
+    let scored = [(0.5, true), (0.5, false), (0.5, true), (0.5, false)];
+    assert_close(roc_auc(&scored), 0.5);
+    // a single threshold, precision is the share of attacks
+    assert_close(pr_auc(&scored), 0.5);
+}
+
+#[test]
+fn perfect_ranking() {
+    let scored = [(0.9, true), (0.8, true), (0.2, false), (0.1, false)];
+    assert_close(roc_auc(&scored), 1.0);
+    assert_close(pr_auc(&scored), 1.0);
+}
+
+#[test]
+fn inverted_ranking() {
+    let scored = [(0.1, true), (0.2, true), (0.8, false), (0.9, false)];
+    assert_close(roc_auc(&scored), 0.0);
+}
+
+#[test]
+fn partial_ranking() {
+    // 3 of the 4 attack/benign pairs are ordered
+    let scored = [(0.9, true), (0.8, false), (0.7, true), (0.1, false)];
+    assert_close(roc_auc(&scored), 0.75);
+    // precision 1 at the first attack, 2/3 at the second
+    assert_close(pr_auc(&scored), 0.5 + 0.5 * 2.0 / 3.0);
+}
+
+#[test]
+fn ties_between_classes_count_half() {
+    let scored = [(0.9, true), (0.5, true), (0.5, false), (0.1, false)];
+    assert_close(roc_auc(&scored), 3.5 / 4.0);
+    // both flows scored 0.5 are reached at once, precision 2/3
+    assert_close(pr_auc(&scored), 0.5 + 0.5 * 2.0 / 3.0);
+}
+
+// predicts the attack class with probability 0.8 for flows to port 80, benign otherwise
+struct PortClassifier {
+    labels: LabelVocab,
+}
+
+impl Classifier for PortClassifier {
+    fn labels(&self) -> &LabelVocab {
+        &self.labels
+    }
+
+    fn predict_batch(&self, items: Vec<IDSItem>) -> Vec<Prediction> {
+        items
+            .iter()
+            .map(|item| {
+                let probabilities = if item.dst_port == 80 {
+                    vec![0.2, 0.8]
+                } else {
+                    vec![0.9, 0.1]
+                };
+                Prediction::new(&self.labels, probabilities)
+            })
+            .collect()
+    }
+}
+
+const FLOWS_CSV: &str = "\
+Dst Port,Total Length of Bwd Packet,Bwd IAT Total,Bwd IAT Mean,Bwd Packet Length Mean,Bwd Packet Length Std,Label
+80,0,0,0,0,0,DoS Hulk
+443,0,0,0,0,0,BENIGN
+80,0,0,0,0,0,Heartbleed
+";
+
+#[test]
+fn unknown_labels_are_counted() {
+    let items: Vec<IDSItem> = ReaderBuilder::new()
+        .has_headers(true)
+        .from_reader(FLOWS_CSV.as_bytes())
+        .deserialize()
+        .collect::<Result<_, _>>()
+        .expect("CSV rows should be valid items");
+    let classifier = PortClassifier {
+        labels: LabelVocab::new(vec!["BENIGN".to_string(), "DoS Hulk".to_string()]),
+    };
+
+    let report = EvaluationReport::evaluate(&classifier, &InMemDataset::new(items), 2);
+    assert_eq!(report.unknown, 1);
+    assert_eq!(report.confusion, vec![vec![1, 0], vec![0, 1]]);
+    assert_close(report.accuracy, 1.0);
+    // the unknown attack is still ranked as an attack
+    assert_close(report.attack.roc_auc, 1.0);
+}
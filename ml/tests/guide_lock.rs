@@ -0,0 +1,36 @@
+// Loads the model used by the daemon, trained and saved before the label vocabulary, feature spec,
+// normalizer and configurable layers existed, and scores one flow with it.
+
+use burn::backend::{ndarray::NdArrayDevice, NdArray};
+use csv::ReaderBuilder;
+use ml::{data::IDSItem, inference::Detector, labels::LabelVocab};
+
+const GUIDE_LOCK: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/guide.lock");
+
+// the columns of the default feature spec
+const FLOW_CSV: &str = "\
+Dst Port,Total Length of Bwd Packet,Bwd IAT Total,Bwd IAT Mean,Bwd Packet Length Mean,Bwd Packet Length Std
+80,3100,4000,2000,1033.3334,808.29037
+";
+
+fn flow() -> IDSItem {
+    ReaderBuilder::new()
+        .has_headers(true)
+        .from_reader(FLOW_CSV.as_bytes())
+        .deserialize()
+        .next()
+        .expect("CSV should contain a row")
+        .expect("CSV row should be a valid item")
+}
+
+#[test]
+fn guide_lock_loads_and_predicts() {
+    let detector = Detector::<NdArray>::new(GUIDE_LOCK, NdArrayDevice::default());
+    assert_eq!(detector.labels().labels, LabelVocab::default().labels);
+
+    let prediction = detector.predict(flow());
+    assert_eq!(prediction.probabilities.len(), 2);
+    let total: f32 = prediction.probabilities.iter().sum();
+    assert!((total - 1.0).abs() < 1e-3, "probabilities sum to {total}");
+    assert!(["BENIGN", "ATTACK"].contains(&prediction.label.as_str()));
+}
@@ -0,0 +1,124 @@
+// Replays a known TCP flow through the kernel flow accounting and checks that the features built
+// by the daemon match the CIC-IDS2017 row describing the same flow.
+//
+// The expected row follows the CICFlowMeter definitions used to build the dataset rather than the
+// formulas of flow.rs: packet lengths count L4 payload bytes (0 for the handshake and the FIN),
+// IATs are in microseconds and standard deviations are sample ones.
+
+use csv::ReaderBuilder;
+use hackathon_ids_common::{
+    ipv4_mapped, payload_len, EventInfo, FlowInfo, FlowKey, DIR_BWD, DIR_FWD, IPPROTO_TCP, TCP_ACK,
+    TCP_FIN, TCP_PSH, TCP_SYN,
+};
+use ml::{
+    data::IDSItem,
+    features::{Feature, FeatureSpec},
+};
+
+const IPV4_HDR_LEN: u64 = 20;
+
+// (direction, IP total length, TCP header length, bpf_ktime_get_ns timestamp, TCP flags).
+// TCP headers carry options, 40 bytes on SYNs (MSS, SACK, timestamps, window scale) and 32 after
+const PACKETS: [(u8, u64, u64, u64, u8); 6] = [
+    (DIR_FWD, 60, 40, 1_000_000_000, TCP_SYN),
+    (DIR_BWD, 60, 40, 1_000_500_000, TCP_SYN | TCP_ACK),
+    (DIR_FWD, 52, 32, 1_001_000_000, TCP_ACK),
+    (DIR_BWD, 1500, 32, 1_002_500_000, TCP_PSH | TCP_ACK),
+    (DIR_BWD, 152, 32, 1_004_500_000, TCP_PSH | TCP_ACK),
+    (DIR_FWD, 52, 32, 1_006_000_000, TCP_FIN | TCP_ACK),
+];
+
+// the same flow as found in the training CSV: payloads of 0 bytes forward and 0, 1448 and 100
+// bytes backward, IAT gaps of 1000 and 5000 us forward and 2000 and 2000 us backward
+const EXPECTED_CSV: &str = "\
+Dst Port,Total Fwd Packet,Total Bwd packets,Total Length of Fwd Packet,Total Length of Bwd Packet,\
+Fwd Packet Length Max,Fwd Packet Length Min,Fwd Packet Length Mean,Fwd Packet Length Std,\
+Bwd Packet Length Max,Bwd Packet Length Min,Bwd Packet Length Mean,Bwd Packet Length Std,\
+Fwd IAT Total,Fwd IAT Mean,Fwd IAT Std,Bwd IAT Total,Bwd IAT Mean,Bwd IAT Std,\
+FIN Flag Count,SYN Flag Count,RST Flag Count,PSH Flag Count,ACK Flag Count,URG Flag Count,Label
+80,3,3,0,1548,0,0,0,0,1448,0,516,808.68288,\
+6000,3000,2828.4271,4000,2000,0,1,2,0,2,5,0,BENIGN
+";
+
+fn replay() -> EventInfo {
+    let key = FlowKey {
+        l4_proto: IPPROTO_TCP,
+        _pad1: 0,
+        vlan_id: 0,
+        ip_src: ipv4_mapped(u32::from_ne_bytes([192, 168, 1, 1])),
+        ip_dst: ipv4_mapped(u32::from_ne_bytes([192, 168, 1, 2])),
+        port_src: 43512,
+        port_dst: 80,
+    };
+
+    let mut flow = FlowInfo::new(PACKETS[0].3);
+    let mut last = (DIR_FWD, 0, 0);
+    for (direction, ip_len, tcp_hdr_len, ts, flags) in PACKETS {
+        // as computed by the XDP program
+        let len = payload_len(ip_len, IPV4_HDR_LEN, tcp_hdr_len);
+        let iat = flow.update(direction, len, ts, false, flags);
+        last = (direction, len, iat);
+    }
+
+    EventInfo {
+        key,
+        direction: last.0,
+        len: last.1,
+        iat: last.2,
+        flow,
+    }
+}
+
+fn expected_item() -> IDSItem {
+    ReaderBuilder::new()
+        .has_headers(true)
+        .from_reader(EXPECTED_CSV.as_bytes())
+        .deserialize()
+        .next()
+        .expect("CSV should contain a row")
+        .expect("CSV row should be a valid item")
+}
+
+#[test]
+fn event_features_match_dataset_units() {
+    let item = IDSItem::from(&replay());
+    let expected = expected_item();
+
+    let spec = FeatureSpec::new(vec![
+        Feature::DstPort,
+        Feature::TotalFwdPackets,
+        Feature::TotalBwdPackets,
+        Feature::TotalLengthFwdPacket,
+        Feature::TotalLengthBwdPacket,
+        Feature::FwdPacketLengthMax,
+        Feature::FwdPacketLengthMin,
+        Feature::FwdPacketLengthMean,
+        Feature::FwdPacketLengthStd,
+        Feature::BwdPacketLengthMax,
+        Feature::BwdPacketLengthMin,
+        Feature::BwdPacketLengthMean,
+        Feature::BwdPacketLengthStd,
+        Feature::FwdIatTotal,
+        Feature::FwdIatMean,
+        Feature::FwdIatStd,
+        Feature::BwdIatTotal,
+        Feature::BwdIatMean,
+        Feature::BwdIatStd,
+        Feature::FinFlagCount,
+        Feature::SynFlagCount,
+        Feature::RstFlagCount,
+        Feature::PshFlagCount,
+        Feature::AckFlagCount,
+        Feature::UrgFlagCount,
+    ]);
+
+    for feature in &spec.features {
+        let actual = feature.value(&item);
+        let expected = feature.value(&expected);
+        let tolerance = 1e-3 * expected.abs().max(1.0);
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{feature:?}: got {actual}, dataset expects {expected}"
+        );
+    }
+}
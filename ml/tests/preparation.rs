@@ -0,0 +1,92 @@
+// Prepares a small CSV with the defects of the original CIC-IDS2017 files: headers with leading
+// spaces and renamed columns, a header repeated by concatenation, NaN/Infinity values and a label
+// with a mis-encoded dash, and checks the rows dropped and the stratified split.
+
+use csv::ReaderBuilder;
+use ml::{
+    features::{Feature, FeatureSpec},
+    preparation::PrepareConfig,
+};
+use std::{collections::BTreeMap, path::Path};
+
+const HEADER: &[u8] = b" Destination Port, Total Fwd Packets, Flow Bytes/s, Label\n";
+// the dash of the original files is a Windows-1252 en dash, not valid UTF-8
+const WEB_ATTACK: &[u8] = b"Web Attack \x96 Brute Force";
+
+fn raw_csv() -> Vec<u8> {
+    let mut csv = HEADER.to_vec();
+    for i in 0..20 {
+        // only the selected columns have to be finite
+        let rate = if i == 0 { "Infinity" } else { "1000" };
+        csv.extend(format!(" 443, {i}, {rate}, BENIGN\n").as_bytes());
+    }
+    csv.extend(HEADER);
+    for i in 0..10 {
+        csv.extend(format!(" 80, {i}, 1000, ").as_bytes());
+        csv.extend(WEB_ATTACK);
+        csv.push(b'\n');
+    }
+    csv.extend(b" 80, NaN, 1000, BENIGN\n");
+    csv.extend(b" 80, Infinity, 1000, BENIGN\n");
+    csv
+}
+
+// rows of each label in a split
+fn split_labels(path: &Path) -> BTreeMap<String, usize> {
+    let mut reader = ReaderBuilder::new()
+        .has_headers(true)
+        .from_path(path)
+        .expect("Split should be readable");
+    assert_eq!(
+        reader.headers().expect("Split should have a header"),
+        vec!["Dst Port", "Total Fwd Packet", "Label"]
+    );
+
+    let mut labels = BTreeMap::new();
+    for record in reader.records() {
+        let record = record.expect("Split rows should be valid");
+        *labels.entry(record[2].to_string()).or_default() += 1;
+    }
+    labels
+}
+
+#[test]
+fn raw_csv_is_cleaned_and_split() {
+    let dir = std::env::temp_dir().join(format!("ml-preparation-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).expect("Temporary directory should be created");
+    let input = dir.join("raw.csv");
+    std::fs::write(&input, raw_csv()).expect("Raw CSV should be written");
+
+    let config = PrepareConfig::new(FeatureSpec::new(vec![
+        Feature::DstPort,
+        Feature::TotalFwdPackets,
+    ]));
+    let output = dir.join("split");
+    let summary = config
+        .prepare(
+            &[input.to_string_lossy().into_owned()],
+            &output.to_string_lossy(),
+        )
+        .expect("Raw CSV should be prepared");
+
+    assert_eq!(summary.rows, 30);
+    assert_eq!(summary.header_rows, 1);
+    assert_eq!(summary.invalid_rows, 2);
+    let web_attack = "Web Attack - Brute Force".to_string();
+    assert_eq!(
+        summary.labels,
+        BTreeMap::from([("BENIGN".to_string(), 20), (web_attack.clone(), 10)])
+    );
+
+    // 70/15/15 of each label, rounded
+    for (name, benign, attack) in [("train", 14, 7), ("valid", 3, 2), ("test", 3, 1)] {
+        assert_eq!(
+            split_labels(&output.join(format!("{name}.csv"))),
+            BTreeMap::from([("BENIGN".to_string(), benign), (web_attack.clone(), attack)]),
+            "{name} split"
+        );
+    }
+    assert!(output.join("prepare.json").exists());
+
+    std::fs::remove_dir_all(&dir).expect("Temporary directory should be removed");
+}
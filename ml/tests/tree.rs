@@ -0,0 +1,111 @@
+// Grows trees on small separable sets: thresholds are integers between the two classes, the trees
+// survive a round trip through tree.json and the classifier loaded from the artifacts returns a
+// distribution over the labels.
+
+use burn::{config::Config, data::dataset::InMemDataset};
+use csv::ReaderBuilder;
+use ml::{
+    data::IDSItem,
+    features::{Feature, FeatureSpec},
+    inference::Classifier,
+    labels::LabelVocab,
+    tree::{train_trees, Forest, ForestConfig, TreeClassifier},
+};
+
+// the first feature separates the classes between 2.5 and 10.2, the second one is constant
+fn toy_set() -> (Vec<Vec<f32>>, Vec<usize>) {
+    let values = vec![
+        vec![1.5, 5.0],
+        vec![2.5, 5.0],
+        vec![10.2, 5.0],
+        vec![11.0, 5.0],
+    ];
+    (values, vec![0, 0, 1, 1])
+}
+
+#[test]
+fn single_tree_splits_on_an_integer_threshold() {
+    let (values, classes) = toy_set();
+    let forest = ForestConfig::new().fit(&values, &classes, 2);
+
+    assert_eq!(forest.trees.len(), 1);
+    let nodes = &forest.trees[0].nodes;
+    assert_eq!(nodes.len(), 3);
+
+    let root = &nodes[0];
+    assert_eq!(root.feature, 0);
+    // the smallest integer above the last value of the left class
+    assert_eq!(root.threshold, 3);
+
+    let left = &nodes[root.left as usize];
+    let right = &nodes[root.right as usize];
+    assert!(left.is_leaf() && right.is_leaf());
+    assert_eq!(left.counts, vec![2, 0]);
+    assert_eq!(right.counts, vec![0, 2]);
+
+    assert_eq!(forest.probabilities(&[3.0, 5.0], 2), vec![1.0, 0.0]);
+    assert_eq!(forest.probabilities(&[4.0, 5.0], 2), vec![0.0, 1.0]);
+}
+
+#[test]
+fn forest_round_trips_through_json() {
+    let (values, classes) = toy_set();
+    let forest = ForestConfig::new()
+        .with_num_trees(8)
+        .fit(&values, &classes, 2);
+    assert_eq!(forest.trees.len(), 8);
+
+    let path = std::env::temp_dir().join(format!("ml-tree-{}.json", std::process::id()));
+    forest.save(&path).expect("Trees should be saved");
+    let loaded = Forest::load(&path).expect("Trees should be loaded");
+    std::fs::remove_file(&path).expect("Trees should be removed");
+
+    assert_eq!(loaded.to_string(), forest.to_string());
+    for point in &values {
+        assert_eq!(
+            loaded.probabilities(point, 2),
+            forest.probabilities(point, 2)
+        );
+    }
+}
+
+const FLOWS_CSV: &str = "\
+Dst Port,Total Length of Bwd Packet,Bwd IAT Total,Bwd IAT Mean,Bwd Packet Length Mean,Bwd Packet Length Std,Label
+443,1200,0,0,0,0,BENIGN
+443,900,0,0,0,0,BENIGN
+53,80,0,0,0,0,BENIGN
+80,0,0,0,0,0,DoS Hulk
+80,10,0,0,0,0,DoS Hulk
+8080,0,0,0,0,0,DoS Hulk
+";
+
+#[test]
+fn classifier_probabilities_sum_to_one() {
+    let items: Vec<IDSItem> = ReaderBuilder::new()
+        .has_headers(true)
+        .from_reader(FLOWS_CSV.as_bytes())
+        .deserialize()
+        .collect::<Result<_, _>>()
+        .expect("CSV rows should be valid items");
+    let labels = LabelVocab::new(vec!["BENIGN".to_string(), "DoS Hulk".to_string()]);
+    let features = FeatureSpec::new(vec![Feature::DstPort, Feature::TotalLengthBwdPacket]);
+
+    let dir = std::env::temp_dir().join(format!("ml-tree-artifacts-{}", std::process::id()));
+    let artifact_dir = dir.to_string_lossy();
+    train_trees(
+        &artifact_dir,
+        ForestConfig::new().with_num_trees(5),
+        features,
+        labels,
+        &InMemDataset::new(items.clone()),
+    );
+
+    let classifier = TreeClassifier::new(&artifact_dir);
+    for prediction in classifier.predict_batch(items) {
+        assert_eq!(prediction.probabilities.len(), 2);
+        let total: f32 = prediction.probabilities.iter().sum();
+        assert!((total - 1.0).abs() < 1e-5, "probabilities sum to {total}");
+    }
+
+    std::fs::remove_dir_all(&dir).expect("Artifacts should be removed");
+}